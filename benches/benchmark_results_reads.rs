@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use tracing::Span;
+use voterium_backend::counting::strategies::{find_strategy, DEFAULT_STRATEGY};
+use voterium_backend::hash_schemes::HashScheme;
+use voterium_backend::metrics::Metrics;
+use voterium_backend::models::{CountWorkerBallot, CountWorkerMsg};
+use voterium_backend::peppers::Peppers;
+use voterium_backend::utils::{load_voting_config, spawn_count_workers};
+
+// Compares reading the results through a GetCounts round-trip on the Counts
+// Worker channels against loading the published counts snapshots, while a
+// background task keeps the channels busy with votes.
+fn benchmark_results_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Results Reads");
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+    let strategy = find_strategy(DEFAULT_STRATEGY).unwrap();
+    let peppers = Peppers::single(vec![0; 8], HashScheme::Blake2b);
+    let count_workers = rt
+        .block_on(spawn_count_workers(
+            config.choices,
+            "examples/cl_10.csv",
+            &peppers,
+            1,
+            strategy,
+            10_000,
+            &Metrics::new(),
+        ))
+        .unwrap();
+
+    // Mixed load: keep the channel full of votes from 100k voters
+    let vote_workers = count_workers.clone();
+    rt.spawn(async move {
+        let mut i: u128 = 0;
+        loop {
+            let ballot = CountWorkerBallot {
+                choice_key: b'A' + (i % 3) as u8,
+                user_id_hash: i % 100_000,
+            };
+            let sender = vote_workers.shard_sender(ballot.user_id_hash);
+            let msg = CountWorkerMsg::Vote {
+                ballot,
+                span: Span::current(),
+            };
+            if sender.send(msg).await.is_err() {
+                break;
+            }
+            i += 1;
+        }
+    });
+
+    group.bench_function("channel round-trip", |b| {
+        b.iter(|| rt.block_on(count_workers.request_counts()).unwrap())
+    });
+
+    group.bench_function("snapshot", |b| b.iter(|| count_workers.counts()));
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_results_reads);
+criterion_main!(benches);
@@ -1,18 +1,103 @@
-use crate::models::{AppState, Claims};
-use actix_web::{error, http::header, web};
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use crate::errors::{AppError, ErrorCode};
+use crate::jwks::KeyStore;
+use crate::models::{AppState, Claims, JwtConfig, RequiredClaim, Role};
+use actix_web::{
+    error,
+    http::{header, Method},
+    web, HttpRequest,
+};
+use chrono::Utc;
+use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+use serde::Deserialize;
+use tracing::warn;
 
 use actix_web::{
     body::{BoxBody, EitherBody, MessageBody},
     dev::{ServiceRequest, ServiceResponse},
     middleware::Next,
-    Error, HttpMessage,
+    Error, HttpMessage, ResponseError,
 };
 
-const PUBLIC_PATHS: &'static [&str] = &[
-    "/voting/config", 
-    "/voting/results",
-];
+// A route that doesn't need a token, parsed from `[METHOD ]PATTERN`.
+// Pattern segments are matched literally, except `*` which matches any one
+// segment and a trailing `**` which matches any remaining segments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicRoute {
+    // None matches every method
+    pub method: Option<Method>,
+    pub segments: Vec<String>,
+}
+
+impl PublicRoute {
+    pub fn parse(route: &str) -> Option<PublicRoute> {
+        let (method, pattern) = match route.trim().split_once(' ') {
+            Some((method, pattern)) => (Some(method), pattern.trim()),
+            None => (None, route.trim()),
+        };
+        let method = match method {
+            None | Some("*") => None,
+            Some(method) => Some(Method::from_bytes(method.as_bytes()).ok()?),
+        };
+        if !pattern.starts_with('/') {
+            return None;
+        }
+
+        let segments: Vec<String> = pattern[1..].split('/').map(str::to_string).collect();
+        // `**` only makes sense as the last segment
+        if segments[..segments.len() - 1].iter().any(|s| s == "**") {
+            return None;
+        }
+        Some(PublicRoute { method, segments })
+    }
+
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        if self.method.as_ref().is_some_and(|m| m != method) {
+            return false;
+        }
+        let Some(path) = path.strip_prefix('/') else {
+            return false;
+        };
+
+        let mut path_segments = path.split('/');
+        for segment in &self.segments {
+            if segment == "**" {
+                return true;
+            }
+            match path_segments.next() {
+                Some(path_segment) if segment == "*" || segment == path_segment => {}
+                _ => return false,
+            }
+        }
+        path_segments.next().is_none()
+    }
+}
+
+// The token signing algorithms the server accepts
+pub fn parse_algorithm(name: &str) -> Option<Algorithm> {
+    match name {
+        "EdDSA" => Some(Algorithm::EdDSA),
+        "ES256" => Some(Algorithm::ES256),
+        "RS256" => Some(Algorithm::RS256),
+        _ => None,
+    }
+}
+
+pub fn is_public_route(public_routes: &[PublicRoute], method: &Method, path: &str) -> bool {
+    public_routes
+        .iter()
+        .any(|route| route.matches(method, path))
+}
+
+// Browsers send CORS preflights without credentials, so they can never carry
+// a token. The CORS middleware answers them; the actual request that follows
+// is checked against the public routes with its own method.
+fn is_cors_preflight(req: &ServiceRequest) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ORIGIN)
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
 
 pub async fn jwt_middleware<B>(
     app_state: web::Data<AppState>,
@@ -22,27 +107,54 @@ pub async fn jwt_middleware<B>(
 where
     B: MessageBody + 'static,
 {
-    if PUBLIC_PATHS.contains(&req.path()) {
+    if is_cors_preflight(&req)
+        || is_public_route(&app_state.public_routes, req.method(), req.path())
+    {
         // Proceed to the next middleware or handler
         let res = next.call(req).await?;
         return Ok(res.map_into_left_body());
     }
 
-    match validate_jwt(&req, &app_state.decoding_key).await {
+    match validate_jwt(&req, &app_state.key_store, &app_state.jwt_config).await {
         Ok(claims) => {
             req.extensions_mut().insert(claims);
             let res = next.call(req).await?;
             Ok(res.map_into_left_body())
         }
-        Err(_) => {
-            let response = error::ErrorUnauthorized("Unauthorized").error_response();
+        Err(err) => {
+            // The reason is only logged; clients always get the same response
+            warn!(
+                "Rejected token for {} {}: {}",
+                req.method(),
+                req.path(),
+                err
+            );
+            let response = AppError::AuthError {
+                code: ErrorCode::Unauthorized,
+                message: "Unauthorized".to_string(),
+            }
+            .error_response();
             let res = req.into_response(response);
             Ok(res.map_into_right_body())
         }
     }
 }
 
-async fn validate_jwt(req: &ServiceRequest, decoding_key: &DecodingKey) -> Result<Claims, Error> {
+// The claims plus everything else in the payload, for checking the
+// configured required claims
+#[derive(Deserialize)]
+struct TokenPayload {
+    #[serde(flatten)]
+    claims: Claims,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+async fn validate_jwt(
+    req: &ServiceRequest,
+    key_store: &KeyStore,
+    jwt_config: &JwtConfig,
+) -> Result<Claims, Error> {
     let auth_header = req.headers().get(header::AUTHORIZATION);
     let token = match auth_header {
         Some(header_value) => {
@@ -65,12 +177,370 @@ async fn validate_jwt(req: &ServiceRequest, decoding_key: &DecodingKey) -> Resul
         }
     };
 
-    let mut validation = Validation::new(Algorithm::EdDSA);
+    // Pick the verification key by the token's `kid`
+    let token_header = decode_header(&token)
+        .map_err(|err| error::ErrorUnauthorized(format!("Invalid token header: {}", err)))?;
+    let key = key_store
+        .find(token_header.kid.as_deref(), Utc::now().timestamp())
+        .ok_or_else(|| error::ErrorUnauthorized("No valid key for token"))?;
+
+    // Only the key's own algorithm is accepted, and only if it's allowed
+    if !jwt_config.algorithms.contains(&key.algorithm) {
+        return Err(error::ErrorUnauthorized(format!(
+            "Algorithm {:?} is not allowed",
+            key.algorithm
+        )));
+    }
+    let mut validation = Validation::new(key.algorithm);
     validation.validate_exp = true;
+    validation.leeway = jwt_config.leeway_secs;
+
+    let mut required_spec_claims = vec!["exp"];
+    if !jwt_config.issuers.is_empty() {
+        validation.set_issuer(&jwt_config.issuers);
+        required_spec_claims.push("iss");
+    }
+    if !jwt_config.audiences.is_empty() {
+        validation.set_audience(&jwt_config.audiences);
+        required_spec_claims.push("aud");
+    }
+    validation.set_required_spec_claims(&required_spec_claims);
 
     // Decode and validate the JWT
-    let token_data = decode::<Claims>(&token, &decoding_key, &validation)
+    let token_data = decode::<TokenPayload>(&token, &key.decoding_key, &validation)
         .map_err(|err| error::ErrorUnauthorized(format!("Invalid token: {}", err)))?;
 
-    Ok(token_data.claims)
+    for required_claim in &jwt_config.required_claims {
+        check_required_claim(&token_data.claims.extra, required_claim)?;
+    }
+
+    Ok(token_data.claims.claims)
+}
+
+// A required claim must be present and, if a value is configured, equal it
+// (or contain it, for array claims)
+fn check_required_claim(
+    extra: &serde_json::Map<String, serde_json::Value>,
+    required_claim: &RequiredClaim,
+) -> Result<(), Error> {
+    let value = extra.get(&required_claim.name).ok_or_else(|| {
+        error::ErrorUnauthorized(format!("Missing claim {:?}", required_claim.name))
+    })?;
+
+    let expected = match &required_claim.value {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let matches = match value {
+        serde_json::Value::String(s) => s == expected,
+        serde_json::Value::Array(items) => items
+            .iter()
+            .any(|item| item.as_str() == Some(expected.as_str())),
+        _ => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(error::ErrorUnauthorized(format!(
+            "Claim {:?} does not match",
+            required_claim.name
+        )))
+    }
+}
+
+// Returns the request's claims if they grant any of the allowed roles
+pub fn authorize(req: &HttpRequest, allowed: &[Role]) -> crate::errors::Result<Claims> {
+    // Clone the claims out so the extensions borrow isn't held across awaits
+    let claims = req
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        // Only happens if the route was configured as public
+        .ok_or(AppError::AuthError {
+            code: ErrorCode::Unauthorized,
+            message: "Missing token".into(),
+        })?;
+
+    if claims.roles().iter().any(|role| allowed.contains(role)) {
+        Ok(claims)
+    } else {
+        Err(AppError::Forbidden {
+            code: ErrorCode::Forbidden,
+            message: format!("Requires one of the roles {:?}", allowed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwks::VerificationKey;
+    use actix_web::test::TestRequest;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+    use jsonwebtoken::{encode, Algorithm, DecodingKey, EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+    use std::sync::OnceLock;
+
+    // An Ed25519 key pair as (PKCS#8 private key, public key)
+    fn ed25519_key() -> &'static (Vec<u8>, Vec<u8>) {
+        static KEY: OnceLock<(Vec<u8>, Vec<u8>)> = OnceLock::new();
+        KEY.get_or_init(|| {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            (
+                pkcs8.as_ref().to_vec(),
+                key_pair.public_key().as_ref().to_vec(),
+            )
+        })
+    }
+
+    fn key_store() -> KeyStore {
+        KeyStore::new(vec![VerificationKey {
+            kid: Some("k1".to_string()),
+            algorithm: Algorithm::EdDSA,
+            decoding_key: DecodingKey::from_ed_der(&ed25519_key().1),
+            not_before: None,
+            not_after: None,
+        }])
+    }
+
+    fn jwt_config() -> JwtConfig {
+        JwtConfig {
+            algorithms: vec![Algorithm::EdDSA],
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway_secs: 0,
+            required_claims: Vec::new(),
+        }
+    }
+
+    // A voter's claims, plus `extra`
+    fn payload(extra: serde_json::Value) -> serde_json::Value {
+        let mut payload = json!({
+            "sub": "voter",
+            "salt": "AAAAAAAAAAA",
+            "exp": Utc::now().timestamp() + 60,
+        });
+        payload
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        payload
+    }
+
+    fn ed25519_token(payload: &serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("k1".to_string());
+        let key = EncodingKey::from_ed_der(&ed25519_key().0);
+        encode(&header, payload, &key).unwrap()
+    }
+
+    async fn validate(token: &str, jwt_config: &JwtConfig) -> Result<Claims, Error> {
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_srv_request();
+        validate_jwt(&req, &key_store(), jwt_config).await
+    }
+
+    #[actix_web::test]
+    async fn test_valid_token_is_accepted() {
+        let claims = validate(&ed25519_token(&payload(json!({}))), &jwt_config())
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "voter");
+    }
+
+    #[actix_web::test]
+    async fn test_disallowed_algorithms_are_refused() {
+        // Signed with the public key as an HMAC secret, the classic confusion
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("k1".to_string());
+        let key = EncodingKey::from_secret(&ed25519_key().1);
+        let token = encode(&header, &payload(json!({})), &key).unwrap();
+        assert!(validate(&token, &jwt_config()).await.is_err());
+
+        // Unsigned
+        let b64 = |value: serde_json::Value| URL_SAFE_NO_PAD.encode(value.to_string());
+        let token = format!(
+            "{}.{}.",
+            b64(json!({ "alg": "none", "kid": "k1" })),
+            b64(payload(json!({})))
+        );
+        assert!(validate(&token, &jwt_config()).await.is_err());
+
+        // The key's own algorithm, but not an allowed one
+        let jwt_config = JwtConfig {
+            algorithms: vec![Algorithm::ES256],
+            ..jwt_config()
+        };
+        let token = ed25519_token(&payload(json!({})));
+        assert!(validate(&token, &jwt_config).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_issuer_and_audience_must_match() {
+        let jwt_config = JwtConfig {
+            issuers: vec!["idp".to_string()],
+            audiences: vec!["voterium".to_string()],
+            ..jwt_config()
+        };
+        let valid = payload(json!({ "iss": "idp", "aud": "voterium" }));
+        assert!(validate(&ed25519_token(&valid), &jwt_config).await.is_ok());
+
+        for payload in [
+            payload(json!({ "iss": "other", "aud": "voterium" })),
+            payload(json!({ "iss": "idp", "aud": "other" })),
+            payload(json!({ "aud": "voterium" })),
+            payload(json!({ "iss": "idp" })),
+        ] {
+            let token = ed25519_token(&payload);
+            assert!(validate(&token, &jwt_config).await.is_err(), "{}", payload);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_required_claims() {
+        let jwt_config = JwtConfig {
+            required_claims: vec![
+                RequiredClaim {
+                    name: "email_verified".to_string(),
+                    value: None,
+                },
+                RequiredClaim {
+                    name: "groups".to_string(),
+                    value: Some("voters".to_string()),
+                },
+            ],
+            ..jwt_config()
+        };
+        let valid = payload(json!({ "email_verified": true, "groups": ["staff", "voters"] }));
+        assert!(validate(&ed25519_token(&valid), &jwt_config).await.is_ok());
+        let valid = payload(json!({ "email_verified": false, "groups": "voters" }));
+        assert!(validate(&ed25519_token(&valid), &jwt_config).await.is_ok());
+
+        for payload in [
+            payload(json!({ "groups": ["voters"] })),
+            payload(json!({ "email_verified": true })),
+            payload(json!({ "email_verified": true, "groups": ["staff"] })),
+            payload(json!({ "email_verified": true, "groups": 1 })),
+        ] {
+            let token = ed25519_token(&payload);
+            assert!(validate(&token, &jwt_config).await.is_err(), "{}", payload);
+        }
+    }
+
+    fn route(spec: &str) -> PublicRoute {
+        PublicRoute::parse(spec).unwrap_or_else(|| panic!("{:?} should parse", spec))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            route("GET /voting/results"),
+            PublicRoute {
+                method: Some(Method::GET),
+                segments: vec!["voting".to_string(), "results".to_string()],
+            }
+        );
+        assert_eq!(route("  /health/**  ").method, None);
+        assert_eq!(route("* /health/live").method, None);
+        assert_eq!(route("PATCH /x").method, Some(Method::PATCH));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_specs() {
+        for spec in [
+            "",
+            "GET",
+            "GET voting/results",
+            "voting/results",
+            "G(T /voting/results",
+            "GET /voting/**/results",
+            "GET /**/**",
+        ] {
+            assert_eq!(PublicRoute::parse(spec), None, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn test_exact_pattern() {
+        let route = route("GET /voting/results");
+        assert!(route.matches(&Method::GET, "/voting/results"));
+        assert!(!route.matches(&Method::GET, "/voting/results/invalid"));
+        assert!(!route.matches(&Method::GET, "/voting"));
+        assert!(!route.matches(&Method::GET, "/voting/result"));
+        assert!(!route.matches(&Method::GET, "voting/results"));
+    }
+
+    #[test]
+    fn test_trailing_slashes_are_significant() {
+        assert!(!route("GET /voting/results").matches(&Method::GET, "/voting/results/"));
+        assert!(route("GET /voting/results/").matches(&Method::GET, "/voting/results/"));
+        assert!(!route("GET /voting/results/").matches(&Method::GET, "/voting/results"));
+    }
+
+    #[test]
+    fn test_single_segment_wildcard() {
+        let route = route("GET /elections/*/results");
+        assert!(route.matches(&Method::GET, "/elections/2026/results"));
+        assert!(route.matches(&Method::GET, "/elections/board/results"));
+        assert!(!route.matches(&Method::GET, "/elections/results"));
+        assert!(!route.matches(&Method::GET, "/elections/2026/extra/results"));
+        assert!(!route.matches(&Method::GET, "/elections/2026/results/x"));
+    }
+
+    #[test]
+    fn test_trailing_wildcard() {
+        let route = route("GET /receipts/**");
+        assert!(route.matches(&Method::GET, "/receipts"));
+        assert!(route.matches(&Method::GET, "/receipts/"));
+        assert!(route.matches(&Method::GET, "/receipts/abc"));
+        assert!(route.matches(&Method::GET, "/receipts/abc/def"));
+        assert!(!route.matches(&Method::GET, "/receipt"));
+        assert!(!route.matches(&Method::GET, "/other/receipts/abc"));
+    }
+
+    #[test]
+    fn test_methods() {
+        let get = route("GET /voting/config");
+        assert!(get.matches(&Method::GET, "/voting/config"));
+        assert!(!get.matches(&Method::POST, "/voting/config"));
+        assert!(!get.matches(&Method::HEAD, "/voting/config"));
+
+        for any in [route("/voting/config"), route("* /voting/config")] {
+            assert!(any.matches(&Method::GET, "/voting/config"));
+            assert!(any.matches(&Method::POST, "/voting/config"));
+            assert!(any.matches(&Method::DELETE, "/voting/config"));
+        }
+    }
+
+    #[test]
+    fn test_route_lists() {
+        // One entry per method, as listed in PUBLIC_ROUTES
+        let routes = vec![
+            route("GET /voting/anonymous/vote"),
+            route("POST /voting/anonymous/vote"),
+            route("GET /health/**"),
+        ];
+        assert!(is_public_route(
+            &routes,
+            &Method::GET,
+            "/voting/anonymous/vote"
+        ));
+        assert!(is_public_route(
+            &routes,
+            &Method::POST,
+            "/voting/anonymous/vote"
+        ));
+        assert!(!is_public_route(
+            &routes,
+            &Method::PUT,
+            "/voting/anonymous/vote"
+        ));
+        assert!(is_public_route(&routes, &Method::GET, "/health/ready"));
+        assert!(!is_public_route(&routes, &Method::POST, "/health/ready"));
+        assert!(!is_public_route(&[], &Method::GET, "/health/ready"));
+    }
 }
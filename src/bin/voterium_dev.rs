@@ -0,0 +1,384 @@
+// Local development helper: generates Ed25519 key pairs and backend salts,
+// and mints voter tokens that the backend accepts.
+//
+//   cargo run --bin voterium_dev -- keygen --out key --kid dev-1
+//   cargo run --bin voterium_dev -- salt
+//   cargo run --bin voterium_dev -- token --key key.pem --sub alice
+//   cargo run --bin voterium_dev -- tokens --key key.pem --count 100000 --out tokens.txt
+//
+// Anonymous credentials (CREDENTIAL_KEY_PATH):
+//   cargo run --bin voterium_dev -- credential-keygen --out credential_key.pem
+//   curl .../voting/credentials/key > public_key.json
+//   cargo run --bin voterium_dev -- credential-blind --public-key public_key.json > state.json
+//   curl -d '{"blinded_message": ...}' .../voting/credentials/issue
+//   cargo run --bin voterium_dev -- credential-finalize --public-key public_key.json \
+//       --state state.json --blind-signature <blind_signature>
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::Utc;
+use clap::{Args, Parser, Subcommand};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use voterium_backend::credentials::{self, BlindingState, CredentialPublicKey};
+use voterium_backend::models::Claims;
+use voterium_backend::utils::gen_random_b64_string;
+
+// DER prefix of an Ed25519 SubjectPublicKeyInfo; the 32 key bytes follow
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+#[derive(Parser)]
+#[command(about = "Keys, salts and tokens for running voterium_backend locally")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate an Ed25519 key pair as <out>.pem and <out>.pub
+    Keygen {
+        #[arg(long, default_value = "key")]
+        out: String,
+        /// Also print a JWKS containing the public key with this kid
+        #[arg(long)]
+        kid: Option<String>,
+    },
+    /// Print a random base64url salt, e.g. for BACKEND_SALT or a
+    /// BACKEND_PEPPERS entry
+    Salt {
+        #[arg(long, default_value_t = 8)]
+        bytes: usize,
+    },
+    /// Mint one token
+    Token {
+        #[command(flatten)]
+        opts: TokenOpts,
+        #[arg(long)]
+        sub: String,
+        /// Base64url salt; random if not given
+        #[arg(long)]
+        salt: Option<String>,
+    },
+    /// Mint tokens for <sub-prefix>0 .. <sub-prefix><count - 1>, one per
+    /// line, each with a random salt
+    Tokens {
+        #[command(flatten)]
+        opts: TokenOpts,
+        #[arg(long)]
+        count: usize,
+        #[arg(long, default_value = "voter-")]
+        sub_prefix: String,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// Generate an RSA private key PEM for CREDENTIAL_KEY_PATH
+    CredentialKeygen {
+        #[arg(long, default_value = "credential_key.pem")]
+        out: String,
+        #[arg(long, default_value_t = 2048)]
+        bits: usize,
+    },
+    /// Blind a fresh credential message; prints the state to keep for
+    /// credential-finalize, including the blinded_message to send
+    CredentialBlind {
+        /// JSON from GET /voting/credentials/key
+        #[arg(long)]
+        public_key: String,
+    },
+    /// Unblind the blind_signature from POST /voting/credentials/issue and
+    /// print the credential for POST /voting/anonymous/vote
+    CredentialFinalize {
+        #[arg(long)]
+        public_key: String,
+        #[arg(long)]
+        state: String,
+        #[arg(long)]
+        blind_signature: String,
+    },
+}
+
+#[derive(Args)]
+struct TokenOpts {
+    /// Ed25519 private key PEM
+    #[arg(long, default_value = "key.pem")]
+    key: String,
+    #[arg(long)]
+    kid: Option<String>,
+    /// Seconds from now until the token expires
+    #[arg(long, default_value_t = 3600, conflicts_with = "exp")]
+    ttl: i64,
+    /// Absolute expiry as a Unix timestamp
+    #[arg(long)]
+    exp: Option<usize>,
+    /// Add a random jti, for JTI_MAX_USES
+    #[arg(long)]
+    jti: bool,
+    /// Comma separated roles claim, e.g. voter,observer
+    #[arg(long, value_delimiter = ',')]
+    roles: Option<Vec<String>>,
+    #[arg(long)]
+    iss: Option<String>,
+    #[arg(long)]
+    aud: Option<String>,
+}
+
+const BULK_BATCH_SIZE: usize = 16_384;
+
+struct Minter<'a> {
+    opts: &'a TokenOpts,
+    header: Header,
+    key: EncodingKey,
+    exp: usize,
+}
+
+impl<'a> Minter<'a> {
+    fn new(opts: &'a TokenOpts) -> Self {
+        let pem = fs::read(&opts.key)
+            .unwrap_or_else(|err| panic!("Failed to read {}: {}", opts.key, err));
+        let key = EncodingKey::from_ed_pem(&pem).expect("Key must be an Ed25519 private key PEM");
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = opts.kid.clone();
+
+        let exp = opts
+            .exp
+            .unwrap_or_else(|| (Utc::now().timestamp() + opts.ttl) as usize);
+
+        Self {
+            opts,
+            header,
+            key,
+            exp,
+        }
+    }
+
+    fn mint(&self, sub: &str, salt: Option<&str>) -> String {
+        let claims = Claims {
+            sub: sub.to_string(),
+            // hash_user_id expects 8 bytes of base64url
+            salt: salt.map_or_else(|| gen_random_b64_string(8), str::to_string),
+            exp: self.exp,
+            jti: self.opts.jti.then(|| gen_random_b64_string(16)),
+            roles: self.opts.roles.clone(),
+        };
+
+        let mut payload = serde_json::to_value(&claims).expect("Failed to serialize claims");
+        if let Some(iss) = &self.opts.iss {
+            payload["iss"] = iss.clone().into();
+        }
+        if let Some(aud) = &self.opts.aud {
+            payload["aud"] = aud.clone().into();
+        }
+
+        encode(&self.header, &payload, &self.key).expect("Failed to sign token")
+    }
+}
+
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let b64 = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in b64.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+fn keygen(out: &str, kid: Option<&str>) -> io::Result<()> {
+    let pkcs8 =
+        Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("Failed to generate key");
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("Generated key is invalid");
+    let public_key = key_pair.public_key().as_ref();
+
+    let mut spki = ED25519_SPKI_PREFIX.to_vec();
+    spki.extend_from_slice(public_key);
+
+    let private_path = format!("{}.pem", out);
+    let public_path = format!("{}.pub", out);
+    fs::write(&private_path, to_pem("PRIVATE KEY", pkcs8.as_ref()))?;
+    fs::write(&public_path, to_pem("PUBLIC KEY", &spki))?;
+    eprintln!("Wrote {} and {}", private_path, public_path);
+
+    if let Some(kid) = kid {
+        let jwks = serde_json::json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(public_key),
+            }]
+        });
+        println!("{}", serde_json::to_string_pretty(&jwks).unwrap());
+    }
+    Ok(())
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> T {
+    let contents =
+        fs::read_to_string(path).unwrap_or_else(|err| panic!("Failed to read {}: {}", path, err));
+    serde_json::from_str(&contents)
+        .unwrap_or_else(|err| panic!("Failed to parse {}: {}", path, err))
+}
+
+fn read_credential_public_key(path: &str) -> RsaPublicKey {
+    let public_key: CredentialPublicKey = read_json(path);
+    RsaPublicKey::try_from(&public_key).expect("Invalid credential public key")
+}
+
+fn main() -> io::Result<()> {
+    match Cli::parse().command {
+        Command::Keygen { out, kid } => keygen(&out, kid.as_deref())?,
+        Command::Salt { bytes } => println!("{}", gen_random_b64_string(bytes)),
+        Command::Token { opts, sub, salt } => {
+            if let Some(salt) = &salt {
+                let bytes = URL_SAFE_NO_PAD.decode(salt);
+                assert!(
+                    bytes.is_ok_and(|bytes| bytes.len() == 8),
+                    "--salt must be 8 bytes of unpadded base64url"
+                );
+            }
+            println!("{}", Minter::new(&opts).mint(&sub, salt.as_deref()));
+        }
+        Command::Tokens {
+            opts,
+            count,
+            sub_prefix,
+            out,
+        } => {
+            let minter = Minter::new(&opts);
+            let mut writer: BufWriter<Box<dyn Write>> = match &out {
+                Some(path) => BufWriter::new(Box::new(File::create(path)?)),
+                None => BufWriter::new(Box::new(io::stdout().lock())),
+            };
+            // Sign batches across threads, writing each batch in order
+            let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            for batch_start in (0..count).step_by(BULK_BATCH_SIZE) {
+                let batch_end = (batch_start + BULK_BATCH_SIZE).min(count);
+                let chunk_size = (batch_end - batch_start).div_ceil(n_threads);
+                let tokens: Vec<Vec<String>> = std::thread::scope(|scope| {
+                    let handles: Vec<_> = (batch_start..batch_end)
+                        .step_by(chunk_size)
+                        .map(|chunk_start| {
+                            let chunk_end = (chunk_start + chunk_size).min(batch_end);
+                            let minter = &minter;
+                            let sub_prefix = &sub_prefix;
+                            scope.spawn(move || {
+                                (chunk_start..chunk_end)
+                                    .map(|i| minter.mint(&format!("{}{}", sub_prefix, i), None))
+                                    .collect()
+                            })
+                        })
+                        .collect();
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                });
+                for token in tokens.iter().flatten() {
+                    writeln!(writer, "{}", token)?;
+                }
+            }
+            writer.flush()?;
+            if let Some(path) = out {
+                eprintln!("Wrote {} tokens to {}", count, path);
+            }
+        }
+        Command::CredentialKeygen { out, bits } => {
+            let private_key = RsaPrivateKey::new(&mut rand::rngs::OsRng, bits)
+                .expect("Failed to generate RSA key");
+            let pem = private_key
+                .to_pkcs8_pem(LineEnding::LF)
+                .expect("Failed to encode RSA key");
+            fs::write(&out, pem.as_bytes())?;
+            eprintln!("Wrote {}", out);
+        }
+        Command::CredentialBlind { public_key } => {
+            let state = credentials::blind(&read_credential_public_key(&public_key));
+            println!("{}", serde_json::to_string_pretty(&state).unwrap());
+        }
+        Command::CredentialFinalize {
+            public_key,
+            state,
+            blind_signature,
+        } => {
+            let public_key = read_credential_public_key(&public_key);
+            let state: BlindingState = read_json(&state);
+            let credential = credentials::finalize(&public_key, &state, &blind_signature)
+                .expect("Invalid blind signature");
+            println!("{}", serde_json::to_string_pretty(&credential).unwrap());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+        assert!(Cli::try_parse_from([
+            "voterium_dev",
+            "token",
+            "--sub",
+            "a",
+            "--ttl",
+            "1",
+            "--exp",
+            "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_minted_tokens_verify_with_the_generated_key() {
+        let out = std::env::temp_dir()
+            .join(format!("voterium_dev_test_{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        keygen(&out, None).unwrap();
+
+        let key = format!("{}.pem", out);
+        let cli = Cli::try_parse_from([
+            "voterium_dev",
+            "token",
+            "--key",
+            &key,
+            "--sub",
+            "alice",
+            "--jti",
+            "--roles",
+            "voter,observer",
+        ])
+        .unwrap();
+        let Command::Token { opts, sub, salt } = cli.command else {
+            panic!("Expected the token command");
+        };
+        let token = Minter::new(&opts).mint(&sub, salt.as_deref());
+
+        let public_key = fs::read(format!("{}.pub", out)).unwrap();
+        let decoding_key = DecodingKey::from_ed_pem(&public_key).unwrap();
+        let claims = decode::<Claims>(&token, &decoding_key, &Validation::new(Algorithm::EdDSA))
+            .unwrap()
+            .claims;
+        assert_eq!(claims.sub, "alice");
+        assert!(claims.jti.is_some());
+        assert_eq!(claims.roles.unwrap().len(), 2);
+        // The salt hash_user_id expects
+        assert_eq!(URL_SAFE_NO_PAD.decode(&claims.salt).unwrap().len(), 8);
+
+        let _ = fs::remove_file(format!("{}.pem", out));
+        let _ = fs::remove_file(format!("{}.pub", out));
+    }
+}
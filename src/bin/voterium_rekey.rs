@@ -0,0 +1,72 @@
+// Offline re-keying: rewrites a CL so every user_id hash is under the
+// current pepper version, using the same BACKEND_PEPPERS / BACKEND_SALT
+// configuration as the backend. Stop the backend before using --in-place.
+//
+//   cargo run --bin voterium_rekey -- --cl cl.csv --out cl.rekeyed.csv
+//   cargo run --bin voterium_rekey -- --cl cl.csv --in-place
+
+use clap::Parser;
+use dotenv::dotenv;
+use std::fs;
+use voterium_backend::hash_schemes::HashScheme;
+use voterium_backend::ledgers::{read_cl_header, write_rekeyed_cl};
+use voterium_backend::peppers::segments_filepath;
+use voterium_backend::server_config::{ServerArgs, ServerConfig};
+use voterium_backend::utils;
+
+#[derive(Parser)]
+#[command(about = "Rewrite a CL under the current backend pepper")]
+struct Cli {
+    /// Defaults to the backend's CL (SERVER_CONFIG or CL_FILEPATH)
+    #[arg(long)]
+    cl: Option<String>,
+    /// Where to write the re-keyed CL; defaults to <cl>.rekeyed
+    #[arg(long, conflicts_with = "in_place")]
+    out: Option<String>,
+    /// Replace the CL itself
+    #[arg(long)]
+    in_place: bool,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+
+    let cl_filepath = match cli.cl {
+        Some(cl_filepath) => cl_filepath,
+        None => ServerConfig::load(&ServerArgs::default())?.paths.cl,
+    };
+    // Re-keying keeps the CL's hash scheme; a CL without a header predates
+    // them, when every CL used Blake2b
+    let scheme = read_cl_header(&cl_filepath)?.unwrap_or(HashScheme::Blake2b);
+    let peppers = utils::load_peppers(&scheme.id())?;
+
+    let (out_filepath, records) = if cli.in_place {
+        // Write both files aside first. The CL's rename is the commit point:
+        // the rekeyed CL's header names its pepper version, so it's read
+        // correctly even if a crash leaves the old segments file next to it.
+        let tmp_filepath = format!("{}.rekeyed.tmp", cl_filepath);
+        let records = write_rekeyed_cl(&cl_filepath, &tmp_filepath, &peppers)?;
+        fs::rename(&tmp_filepath, &cl_filepath)?;
+        fs::rename(
+            segments_filepath(&tmp_filepath),
+            segments_filepath(&cl_filepath),
+        )?;
+        (cl_filepath, records)
+    } else {
+        let out_filepath = cli
+            .out
+            .unwrap_or_else(|| format!("{}.rekeyed", cl_filepath));
+        let records = write_rekeyed_cl(&cl_filepath, &out_filepath, &peppers)?;
+        (out_filepath, records)
+    };
+
+    eprintln!(
+        "Wrote {} records under pepper version {} to {}",
+        records,
+        peppers.current_version(),
+        out_filepath
+    );
+    Ok(())
+}
@@ -0,0 +1,198 @@
+// CORS for every route group in one middleware, wrapped outside
+// authentication so that its 401s still carry the headers a browser needs to
+// read them. Each request gets the policy of the route group its path falls
+// in; unknown routes get no CORS headers.
+
+use crate::server_config::{CorsConfig, CORS_ROUTE_GROUPS};
+use actix_cors::CorsMiddleware;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+// The route group whose prefix `path` is in, minding segment boundaries so
+// /votingx isn't under /voting
+pub fn route_group(path: &str) -> Option<&'static str> {
+    CORS_ROUTE_GROUPS
+        .iter()
+        .find(|(_, prefix)| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .map(|(group, _)| *group)
+}
+
+pub struct RouteGroupCors {
+    cors_config: CorsConfig,
+}
+
+impl RouteGroupCors {
+    pub fn new(cors_config: &CorsConfig) -> Self {
+        Self {
+            cors_config: cors_config.clone(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RouteGroupCors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RouteGroupCorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        // Every group's CORS middleware calls the same inner service
+        let service = Rc::new(service);
+        let groups = CORS_ROUTE_GROUPS
+            .iter()
+            .map(|(group, _)| {
+                let cors = self
+                    .cors_config
+                    .cors(group)
+                    .new_transform(service.clone())
+                    .into_inner()?;
+                Ok((*group, cors))
+            })
+            .collect::<Result<_, ()>>();
+        ready(groups.map(|groups| RouteGroupCorsMiddleware { service, groups }))
+    }
+}
+
+pub struct RouteGroupCorsMiddleware<S> {
+    service: Rc<S>,
+    groups: Vec<(&'static str, CorsMiddleware<Rc<S>>)>,
+}
+
+impl<S, B> Service<ServiceRequest> for RouteGroupCorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let cors = route_group(req.path())
+            .and_then(|group| self.groups.iter().find(|(name, _)| *name == group));
+        match cors {
+            Some((_, cors)) => cors.call(req),
+            None => {
+                let res = self.service.call(req);
+                Box::pin(async move { Ok(res.await?.map_into_left_body()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AppError, ErrorCode};
+    use actix_web::http::{header, Method, StatusCode};
+    use actix_web::middleware::{from_fn, Next};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse, ResponseError};
+
+    const ORIGIN: &str = "https://vote.example.com";
+
+    // Stands in for the JWT middleware, refusing every request
+    async fn refuse<B: MessageBody + 'static>(
+        req: ServiceRequest,
+        _next: Next<B>,
+    ) -> Result<ServiceResponse, Error> {
+        let response = AppError::AuthError {
+            code: ErrorCode::Unauthorized,
+            message: "Unauthorized".to_string(),
+        }
+        .error_response();
+        Ok(req.into_response(response))
+    }
+
+    fn cors_config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec![ORIGIN.to_string()],
+            ..CorsConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_route_group_minds_segments() {
+        assert_eq!(route_group("/voting/admin/election"), Some("admin"));
+        assert_eq!(route_group("/voting/vote"), Some("voting"));
+        assert_eq!(route_group("/voting"), Some("voting"));
+        assert_eq!(route_group("/votingx"), None);
+        assert_eq!(route_group("/metrics"), Some("metrics"));
+        assert_eq!(route_group("/unknown"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_rejections_inside_carry_cors_headers() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(refuse))
+                .wrap(RouteGroupCors::new(&cors_config()))
+                .route("/voting/vote", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/voting/vote")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            ORIGIN
+        );
+
+        // Unknown routes get none
+        let req = TestRequest::get()
+            .uri("/unknown")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_preflight_is_answered() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(refuse))
+                .wrap(RouteGroupCors::new(&cors_config())),
+        )
+        .await;
+
+        let req = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/metrics")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            ORIGIN
+        );
+    }
+}
@@ -3,14 +3,13 @@
 use crate::errors::Result;
 use crate::models::{Choice, VoteCount};
 use core::str;
-use log::info;
 use memchr::{memchr, memchr_iter};
 use rustc_hash::{FxHashMap, FxHashSet};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Seek;
 use std::io::{BufReader, Read};
-use std::time::Instant;
+use tracing::{debug_span, instrument};
 
 #[derive(Clone)]
 pub struct CLVote {
@@ -29,12 +28,12 @@ fn data_to_lines(data: &[u8]) -> impl Iterator<Item = &str> {
 }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_01(data: &[u8]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
     let mut votes: Vec<CLVote> = Vec::new();
 
     // Parse lines
-    let start_parse = Instant::now();
+    let parse_span = debug_span!("parse").entered();
     for line in data_to_lines(data) {
         let parts: Vec<&str> = line.trim().split(',').collect();
         if parts.len() != 3 {
@@ -53,10 +52,10 @@ pub fn count_votes_01(data: &[u8]) -> Result<Vec<VoteCount>> {
 
         votes.push(vote);
     }
-    let duration_parse = start_parse.elapsed();
+    drop(parse_span);
 
     // Build a map of user_id_hash to their latest vote
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
     let mut latest_votes: HashMap<String, CLVote> = HashMap::new();
     for vote in votes {
         latest_votes
@@ -68,15 +67,15 @@ pub fn count_votes_01(data: &[u8]) -> Result<Vec<VoteCount>> {
             })
             .or_insert(vote);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
     let mut counts: HashMap<String, u32> = HashMap::new();
     for vote in latest_votes.values() {
         *counts.entry(vote.choice.clone()).or_insert(0) += 1;
     }
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -84,10 +83,6 @@ pub fn count_votes_01(data: &[u8]) -> Result<Vec<VoteCount>> {
         .map(|(choice, count)| VoteCount { choice, count })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!("count_votes_1 - total {:?}  -  parse lines and collect votes {:?}, process votes into latest_votes {:?}, count votes {:?}",
-            duration_total, duration_parse, duration_process, duration_count);
-
     Ok(vote_counts)
 }
 
@@ -153,13 +148,10 @@ pub fn count_votes_01(data: &[u8]) -> Result<Vec<VoteCount>> {
 // }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_03(data: &[u8]) -> Result<Vec<VoteCount>> {
-    use std::time::Instant;
-
-    let start_total = Instant::now();
-
     // Since we know the file size, we can preallocate buffers
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     // Prepare buffers for lines and parts to reduce allocations
     let mut latest_votes: HashMap<String, String> = HashMap::new();
@@ -191,15 +183,15 @@ pub fn count_votes_03(data: &[u8]) -> Result<Vec<VoteCount>> {
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash.to_string(), choice.to_string());
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
     let mut counts: HashMap<String, u32> = HashMap::new();
     for choice in latest_votes.values() {
         *counts.entry(choice.clone()).or_insert(0) += 1;
     }
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -207,12 +199,6 @@ pub fn count_votes_03(data: &[u8]) -> Result<Vec<VoteCount>> {
         .map(|(choice, count)| VoteCount { choice, count })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_3 - total {:?} process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -231,11 +217,9 @@ fn count_lines_fast(reader: &mut BufReader<File>) -> Result<usize> {
     Ok(line_count)
 }
 
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_04(data: &[u8]) -> Result<Vec<VoteCount>> {
-    use std::time::Instant;
-
-    let start_total = Instant::now();
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     // Prepare buffers for lines and parts to reduce allocations
     let line_count = data.len() / 33; // Average line length is 33 bytes
@@ -269,15 +253,15 @@ pub fn count_votes_04(data: &[u8]) -> Result<Vec<VoteCount>> {
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash.to_string(), choice.to_string());
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
     let mut counts: HashMap<String, u32> = HashMap::new();
     for choice in latest_votes.values() {
         *counts.entry(choice.clone()).or_insert(0) += 1;
     }
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -285,12 +269,6 @@ pub fn count_votes_04(data: &[u8]) -> Result<Vec<VoteCount>> {
         .map(|(choice, count)| VoteCount { choice, count })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_4 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -372,10 +350,9 @@ pub fn count_votes_04(data: &[u8]) -> Result<Vec<VoteCount>> {
 // }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_06(data: &[u8]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -415,15 +392,15 @@ pub fn count_votes_06(data: &[u8]) -> Result<Vec<VoteCount>> {
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash, choice);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
     let mut counts: HashMap<&[u8], u32> = HashMap::new();
     for choice in latest_votes.values() {
         *counts.entry(*choice).or_insert(0) += 1;
     }
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -434,12 +411,6 @@ pub fn count_votes_06(data: &[u8]) -> Result<Vec<VoteCount>> {
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_6 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -582,10 +553,9 @@ pub fn count_votes_06(data: &[u8]) -> Result<Vec<VoteCount>> {
 // }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_08(data: &[u8]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
     latest_votes.reserve(data.len() / 33); // Average line length is 33 bytes
@@ -626,15 +596,15 @@ pub fn count_votes_08(data: &[u8]) -> Result<Vec<VoteCount>> {
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash, choice);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
     let mut counts: HashMap<&[u8], u32> = HashMap::new();
     for choice in latest_votes.values() {
         *counts.entry(*choice).or_insert(0) += 1;
     }
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -645,12 +615,6 @@ pub fn count_votes_08(data: &[u8]) -> Result<Vec<VoteCount>> {
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_8 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -763,10 +727,9 @@ fn find_new_line_pos(bytes: &[u8]) -> Option<usize> {
 // }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_10(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -806,10 +769,10 @@ pub fn count_votes_10(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash, choice);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: HashMap<&[u8], u32> =
         HashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -817,7 +780,7 @@ pub fn count_votes_10(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
     for choice in latest_votes.values() {
         *counts.entry(*choice).or_insert(0) += 1;
     }
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -828,19 +791,13 @@ pub fn count_votes_10(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_10 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_11(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -880,10 +837,10 @@ pub fn count_votes_11(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash, choice);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -892,7 +849,7 @@ pub fn count_votes_11(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_insert(0) += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -903,20 +860,13 @@ pub fn count_votes_11(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_11 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::iter_kv_map)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_12(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -956,10 +906,10 @@ pub fn count_votes_12(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash, choice);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -978,7 +928,7 @@ pub fn count_votes_12(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_insert(0) += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -989,20 +939,13 @@ pub fn count_votes_12(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_12 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_13(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -1029,10 +972,10 @@ pub fn count_votes_13(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // Overwrite the latest vote for the user
         latest_votes.insert(user_id_hash, choice);
     }
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1041,7 +984,7 @@ pub fn count_votes_13(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_insert(0) += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1052,20 +995,13 @@ pub fn count_votes_13(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_13 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_14(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -1088,10 +1024,10 @@ pub fn count_votes_14(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1100,7 +1036,7 @@ pub fn count_votes_14(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_insert(0) += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1111,20 +1047,13 @@ pub fn count_votes_14(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_14 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_15(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -1158,10 +1087,10 @@ pub fn count_votes_15(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1170,7 +1099,7 @@ pub fn count_votes_15(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_insert(0) += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1181,12 +1110,6 @@ pub fn count_votes_15(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_15 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -1199,11 +1122,10 @@ fn fast_split(data: &[u8], delimiter: u8) -> impl Iterator<Item = &[u8]> {
     })
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_16(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> = FxHashMap::default();
 
@@ -1220,10 +1142,10 @@ pub fn count_votes_16(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         }
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1232,7 +1154,7 @@ pub fn count_votes_16(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_insert(0) += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1243,12 +1165,6 @@ pub fn count_votes_16(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_16 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -1310,11 +1226,10 @@ pub fn count_votes_16(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
 //     Ok(vote_counts)
 // }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_18(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let max_n_lines = data.len() / 33; // Average line length is 33 bytes
     let mut latest_votes: FxHashMap<&[u8], &[u8]> =
@@ -1333,10 +1248,10 @@ pub fn count_votes_18(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         }
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1345,7 +1260,7 @@ pub fn count_votes_18(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1356,22 +1271,15 @@ pub fn count_votes_18(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_18 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_19(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line;
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> =
         FxHashMap::with_capacity_and_hasher(max_n_lines, Default::default());
@@ -1387,10 +1295,10 @@ pub fn count_votes_19(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     });
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1399,7 +1307,7 @@ pub fn count_votes_19(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1410,22 +1318,15 @@ pub fn count_votes_19(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_19 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_20(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line;
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> =
         FxHashMap::with_capacity_and_hasher(max_n_lines, Default::default());
@@ -1450,10 +1351,10 @@ pub fn count_votes_20(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1462,7 +1363,7 @@ pub fn count_votes_20(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1473,12 +1374,6 @@ pub fn count_votes_20(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_20 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -1547,12 +1442,11 @@ pub enum MatchChar {
 // }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_22(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line;
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let mut latest_votes: FxHashMap<&[u8], &[u8]> =
         FxHashMap::with_capacity_and_hasher(max_n_lines, Default::default());
@@ -1571,10 +1465,10 @@ pub fn count_votes_22(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
             latest_votes.insert(user_id_hash, choice);
         });
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1583,7 +1477,7 @@ pub fn count_votes_22(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1594,20 +1488,13 @@ pub fn count_votes_22(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_22 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_23(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line;
@@ -1622,10 +1509,10 @@ pub fn count_votes_23(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1634,7 +1521,7 @@ pub fn count_votes_23(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1645,20 +1532,13 @@ pub fn count_votes_23(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_23 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_24(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line;
@@ -1674,10 +1554,10 @@ pub fn count_votes_24(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1686,7 +1566,7 @@ pub fn count_votes_24(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1697,20 +1577,13 @@ pub fn count_votes_24(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_24 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_25(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line;
@@ -1730,10 +1603,10 @@ pub fn count_votes_25(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1742,7 +1615,7 @@ pub fn count_votes_25(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1753,22 +1626,15 @@ pub fn count_votes_25(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_25 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
 use bstr::ByteSlice;
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_26(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line; // Average line length is 33 bytes
@@ -1792,10 +1658,10 @@ pub fn count_votes_26(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         latest_votes.insert(user_id_hash, choice);
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     let mut counts: FxHashMap<&[u8], u32> =
         FxHashMap::from_iter(choices.iter().map(|choice| (choice.key.as_bytes(), 0)));
@@ -1804,7 +1670,7 @@ pub fn count_votes_26(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         *counts.entry(*choice).or_default() += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = counts
@@ -1815,31 +1681,24 @@ pub fn count_votes_26(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-    info!(
-        "count_votes_26 - total {:?}, process votes to latest_votes {:?}, count votes {:?}",
-        duration_total, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_borrow)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_27(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     // Step 2: Create a mapping from choice key to index
-    let start_choice_mapping = Instant::now();
+    let choice_mapping_span = debug_span!("choice_mapping").entered();
     let mut choice_to_index: FxHashMap<&[u8], usize> =
         FxHashMap::with_capacity_and_hasher(choices.len(), Default::default());
 
     for (idx, choice) in choices.iter().enumerate() {
         choice_to_index.insert(choice.key.as_bytes(), idx);
     }
-    let duration_choice_mapping = start_choice_mapping.elapsed();
+    drop(choice_mapping_span);
 
     // Step 3: Process lines to determine the latest vote per user
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     let min_bytes_per_line = 32;
     let max_n_lines = data.len() / min_bytes_per_line; // Average line length is 33 bytes
@@ -1861,10 +1720,10 @@ pub fn count_votes_27(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // If the choice_bytes do not correspond to any known choice, you can handle it accordingly
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Step 4: Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     // Initialize a counts vector
     let mut counts = vec![0u32; choices.len()];
@@ -1874,7 +1733,7 @@ pub fn count_votes_27(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         counts[choice_idx] += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Step 5: Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = choices
@@ -1886,32 +1745,24 @@ pub fn count_votes_27(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-
-    info!(
-        "count_votes_27 - total {:?}, choice mapping: {:?}, process: {:?}, count: {:?}",
-        duration_total, duration_choice_mapping, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_28(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     // Step 2: Create a mapping from choice key to index
-    let start_choice_mapping = Instant::now();
+    let choice_mapping_span = debug_span!("choice_mapping").entered();
     let mut choice_to_index: FxHashMap<&[u8], usize> =
         FxHashMap::with_capacity_and_hasher(choices.len(), Default::default());
 
     for (idx, choice) in choices.iter().enumerate() {
         choice_to_index.insert(choice.key.as_bytes(), idx);
     }
-    let duration_choice_mapping = start_choice_mapping.elapsed();
+    drop(choice_mapping_span);
 
     // Step 3: Process lines to determine the latest vote per user
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     const RECORD_SIZE: usize = 33;
     let max_n_lines = data.len() / RECORD_SIZE + 1;
@@ -1933,10 +1784,10 @@ pub fn count_votes_28(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // If the choice_bytes do not correspond to any known choice, you can handle it accordingly
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Step 4: Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     // Initialize a counts vector
     let mut counts = vec![0u32; choices.len()];
@@ -1946,7 +1797,7 @@ pub fn count_votes_28(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         counts[choice_idx] += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Step 5: Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = choices
@@ -1958,32 +1809,24 @@ pub fn count_votes_28(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-
-    info!(
-        "count_votes_28 - total {:?}, choice mapping: {:?}, process: {:?}, count: {:?}",
-        duration_total, duration_choice_mapping, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_29(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     // Step 2: Create a mapping from choice key to index
-    let start_choice_mapping = Instant::now();
+    let choice_mapping_span = debug_span!("choice_mapping").entered();
     let mut choice_to_index: FxHashMap<&[u8], usize> =
         FxHashMap::with_capacity_and_hasher(choices.len(), Default::default());
 
     for (idx, choice) in choices.iter().enumerate() {
         choice_to_index.insert(choice.key.as_bytes(), idx);
     }
-    let duration_choice_mapping = start_choice_mapping.elapsed();
+    drop(choice_mapping_span);
 
     // Step 3: Process lines to determine the latest vote per user
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     const RECORD_SIZE: usize = 33;
     let max_n_lines = data.len() / RECORD_SIZE + 1;
@@ -2006,10 +1849,10 @@ pub fn count_votes_29(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // If the choice_bytes do not correspond to any known choice, you can handle it accordingly
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Step 4: Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     // Initialize a counts vector
     let mut counts = vec![0u32; choices.len()];
@@ -2019,7 +1862,7 @@ pub fn count_votes_29(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         counts[choice_idx] += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Step 5: Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = choices
@@ -2031,34 +1874,26 @@ pub fn count_votes_29(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-
-    info!(
-        "count_votes_29 - total {:?}, choice mapping: {:?}, process: {:?}, count: {:?}",
-        duration_total, duration_choice_mapping, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
 use ahash::AHashMap;
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_30(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     // Step 2: Create a mapping from choice key to index
-    let start_choice_mapping = Instant::now();
+    let choice_mapping_span = debug_span!("choice_mapping").entered();
     let mut choice_to_index: FxHashMap<&[u8], usize> =
         FxHashMap::with_capacity_and_hasher(choices.len(), Default::default());
 
     for (idx, choice) in choices.iter().enumerate() {
         choice_to_index.insert(choice.key.as_bytes(), idx);
     }
-    let duration_choice_mapping = start_choice_mapping.elapsed();
+    drop(choice_mapping_span);
 
     // Step 3: Process lines to determine the latest vote per user
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     const RECORD_SIZE: usize = 33;
     let max_n_lines = data.len() / RECORD_SIZE + 1;
@@ -2080,10 +1915,10 @@ pub fn count_votes_30(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // If the choice_bytes do not correspond to any known choice, you can handle it accordingly
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Step 4: Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     // Initialize a counts vector
     let mut counts = vec![0u32; choices.len()];
@@ -2093,7 +1928,7 @@ pub fn count_votes_30(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         counts[choice_idx] += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Step 5: Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = choices
@@ -2105,32 +1940,24 @@ pub fn count_votes_30(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-
-    info!(
-        "count_votes_30 - total {:?}, choice mapping: {:?}, process: {:?}, count: {:?}",
-        duration_total, duration_choice_mapping, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_31(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     // Step 2: Create a mapping from choice key to index
-    let start_choice_mapping = Instant::now();
+    let choice_mapping_span = debug_span!("choice_mapping").entered();
     let mut choice_to_index: FxHashMap<u8, usize> =
         FxHashMap::with_capacity_and_hasher(choices.len(), Default::default());
 
     for (idx, choice) in choices.iter().enumerate() {
         choice_to_index.insert(choice.key.as_bytes()[0], idx);
     }
-    let duration_choice_mapping = start_choice_mapping.elapsed();
+    drop(choice_mapping_span);
 
     // Step 3: Process lines to determine the latest vote per user
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     const RECORD_SIZE: usize = 33;
     let max_n_lines = data.len() / RECORD_SIZE + 1;
@@ -2152,10 +1979,10 @@ pub fn count_votes_31(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         // If the choice_bytes do not correspond to any known choice, you can handle it accordingly
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Step 4: Count the votes
-    let start_count = Instant::now();
+    let count_span = debug_span!("count").entered();
 
     // Initialize a counts vector
     let mut counts = vec![0u32; choices.len()];
@@ -2165,7 +1992,7 @@ pub fn count_votes_31(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         counts[choice_idx] += 1;
     }
 
-    let duration_count = start_count.elapsed();
+    drop(count_span);
 
     // Step 5: Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = choices
@@ -2177,13 +2004,6 @@ pub fn count_votes_31(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-
-    info!(
-        "count_votes_31 - total  {:?} choice mapping: {:?}, process: {:?}, count: {:?}",
-        duration_total, duration_choice_mapping, duration_process, duration_count
-    );
-
     Ok(vote_counts)
 }
 
@@ -2301,22 +2121,21 @@ pub fn count_votes_31(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
 //     Ok(vote_counts)
 // }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::redundant_pattern_matching)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_34(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
-    let start_total = Instant::now();
-
     // Step 2: Create a mapping from choice key to index
-    let start_choice_mapping = Instant::now();
+    let choice_mapping_span = debug_span!("choice_mapping").entered();
     let mut choice_to_index: FxHashMap<u8, usize> =
         FxHashMap::with_capacity_and_hasher(choices.len(), Default::default());
 
     for (idx, choice) in choices.iter().enumerate() {
         choice_to_index.insert(choice.key.as_bytes()[0], idx);
     }
-    let duration_choice_mapping = start_choice_mapping.elapsed();
+    drop(choice_mapping_span);
 
     // Step 3: Process lines to determine the latest vote per user
-    let start_process = Instant::now();
+    let process_span = debug_span!("process").entered();
 
     // // Using u128 as key for better performance
     const RECORD_SIZE: usize = 33;
@@ -2341,7 +2160,7 @@ pub fn count_votes_34(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         }
     }
 
-    let duration_process = start_process.elapsed();
+    drop(process_span);
 
     // Step 5: Convert counts to a vector of VoteCount
     let vote_counts: Vec<VoteCount> = choices
@@ -2353,23 +2172,19 @@ pub fn count_votes_34(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
         })
         .collect();
 
-    let duration_total = start_total.elapsed();
-
-    info!(
-        "count_votes_34 - total {:?}, map choices {:?}, process: {:?}",
-        duration_total, duration_choice_mapping, duration_process
-    );
-
     Ok(vote_counts)
 }
 
 use super::utils::indexed_counts_to_vote_counts;
 use crate::counting::utils::{
-    init_seen_hashset, make_choices_lookup, user_id_hash_u128_from_bytes,
+    init_seen_hashset, make_choices_lookup, make_sharded_latest_votes,
+    user_id_hash_u128_from_bytes, voter_partition, InvalidRecords, ShardedLatestVotes,
 };
+use std::collections::hash_map::Entry;
 const RECORD_SIZE: usize = 33;
 
 #[allow(dead_code)]
+#[instrument(level = "debug", skip_all)]
 pub fn count_votes_35(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
     let choice_to_index = make_choices_lookup(choices);
     let mut seen_voters = init_seen_hashset(data);
@@ -2393,3 +2208,143 @@ pub fn count_votes_35(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>
 
     Ok(vote_counts)
 }
+
+#[instrument(level = "debug", skip_all)]
+pub fn count_votes_36(data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
+    // Parallel version of count_votes_35 using every available core
+    let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    count_votes_36_with_threads(data, choices, n_threads)
+}
+
+#[instrument(level = "debug", skip_all)]
+pub fn count_votes_36_with_threads(
+    data: &[u8],
+    choices: &[Choice],
+    n_threads: usize,
+) -> Result<Vec<VoteCount>> {
+    // Voters are partitioned by hash, so each thread owns a disjoint set of
+    // voters and can decide their latest vote without talking to the others.
+    // Every thread reads the shared ledger newest record first and skips the
+    // voters outside its own partition, so nothing is copied.
+    let n_threads = n_threads.max(1);
+    let choice_to_index = make_choices_lookup(choices);
+    let partition_capacity = data.len() / RECORD_SIZE / n_threads + 1;
+
+    let partial_counts: Vec<Vec<u32>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_threads)
+            .map(|partition| {
+                let choice_to_index = &choice_to_index;
+                scope.spawn(move || {
+                    let mut seen_voters: FxHashSet<u128> =
+                        FxHashSet::with_capacity_and_hasher(partition_capacity, Default::default());
+                    let mut counts = vec![0u32; choices.len()];
+
+                    for line in data.chunks_exact(RECORD_SIZE).rev() {
+                        let user_id_hash = user_id_hash_u128_from_bytes(&line[..16]);
+                        if voter_partition(user_id_hash, n_threads) != partition {
+                            continue;
+                        }
+
+                        let is_latest_vote = seen_voters.insert(user_id_hash);
+                        if !is_latest_vote {
+                            continue;
+                        }
+
+                        let choice = line[31];
+                        if let Some(choice_idx) = choice_to_index.get(&choice) {
+                            counts[*choice_idx] += 1;
+                        }
+                    }
+
+                    counts
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Counting thread panicked"))
+            .collect()
+    });
+
+    let mut counts = vec![0u32; choices.len()];
+    for partial in partial_counts {
+        for (total, count) in counts.iter_mut().zip(partial) {
+            *total += count;
+        }
+    }
+
+    let vote_counts = indexed_counts_to_vote_counts(&counts, choices);
+
+    Ok(vote_counts)
+}
+
+// The latest votes the Counts Workers start from, read the same way as the
+// matching count_votes_* function. Votes for unknown choices and malformed
+// records are kept as INVALID_CHOICE_IDX so they can be counted as invalid.
+#[instrument(level = "debug", skip_all)]
+pub fn latest_votes_35(
+    data: &[u8],
+    choices: &[Choice],
+    n_shards: usize,
+) -> Result<ShardedLatestVotes> {
+    Ok(make_sharded_latest_votes(
+        data,
+        &make_choices_lookup(choices),
+        n_shards,
+    ))
+}
+
+#[instrument(level = "debug", skip_all)]
+pub fn latest_votes_36(
+    data: &[u8],
+    choices: &[Choice],
+    n_shards: usize,
+) -> Result<ShardedLatestVotes> {
+    // Partitioned like count_votes_36_with_threads, with a thread per shard
+    let choice_to_index = make_choices_lookup(choices);
+    let shard_capacity = data.len() / RECORD_SIZE / n_shards + 1;
+
+    let shards: Vec<(FxHashMap<u128, usize>, InvalidRecords)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_shards)
+            .map(|shard| {
+                let choice_to_index = &choice_to_index;
+                scope.spawn(move || {
+                    let mut latest_votes: FxHashMap<u128, usize> =
+                        FxHashMap::with_capacity_and_hasher(shard_capacity, Default::default());
+                    let mut invalid_records = InvalidRecords::default();
+
+                    for line in data.rchunks_exact(RECORD_SIZE) {
+                        let user_id_hash = user_id_hash_u128_from_bytes(&line[..16]);
+                        if voter_partition(user_id_hash, n_shards) != shard {
+                            continue;
+                        }
+
+                        if let Entry::Vacant(v) = latest_votes.entry(user_id_hash) {
+                            v.insert(invalid_records.choice_idx(line, choice_to_index));
+                        }
+                    }
+
+                    (latest_votes, invalid_records)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Counting thread panicked"))
+            .collect()
+    });
+
+    let mut invalid_records = InvalidRecords::default();
+    let sharded_latest_votes = shards
+        .into_iter()
+        .map(|(latest_votes, shard_invalid_records)| {
+            invalid_records.add(&shard_invalid_records);
+            latest_votes
+        })
+        .collect();
+    invalid_records.warn(data);
+
+    Ok(sharded_latest_votes)
+}
@@ -1,4 +1,6 @@
 pub mod counting_funcs;
 pub use counting_funcs::count_votes_35 as count_votes;
-pub mod utils;
+pub mod strategies;
+#[cfg(test)]
 mod tests;
+pub mod utils;
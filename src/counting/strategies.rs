@@ -0,0 +1,377 @@
+// Registry of the counting implementations in counting_funcs, so callers can
+// pick one by name and know what it assumes about the CL and the choices
+
+use crate::counting::counting_funcs::*;
+use crate::counting::utils::ShardedLatestVotes;
+use crate::errors::{AppError, Result};
+use crate::models::{Choice, VoteCount};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    // Splits each line on commas, so any field width works
+    CommaSeparated,
+    // Splits on newlines, then reads the user_id_hash from bytes 0..16 and
+    // the choice from byte 31 to the end of the line
+    FixedOffsets,
+    // Reads 33 byte records without looking for newlines
+    FixedWidth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatestVote {
+    // Compares timestamps, so the CL doesn't have to be in order
+    ByTimestamp,
+    // The last record in the CL wins
+    ByLedgerOrder,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StrategyMetadata {
+    pub name: &'static str,
+    pub record_format: RecordFormat,
+    pub latest_vote: LatestVote,
+    // Without the configured choices, only choices that received votes
+    // appear in the counts
+    pub uses_choices: bool,
+    // Only the first byte of a choice key is compared
+    pub single_byte_keys: bool,
+    pub multi_threaded: bool,
+}
+
+pub trait CountingStrategy: Send + Sync {
+    fn metadata(&self) -> &StrategyMetadata;
+
+    fn count_votes(&self, data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>>;
+
+    // The latest vote of each voter split into `n_shards` by voter_partition,
+    // which the Counts Workers start from. Only the strategies that read the
+    // CL the way the workers apply votes can make them.
+    fn latest_votes(
+        &self,
+        data: &[u8],
+        choices: &[Choice],
+        n_shards: usize,
+    ) -> Result<ShardedLatestVotes>;
+
+    fn makes_latest_votes(&self) -> bool;
+
+    fn name(&self) -> &'static str {
+        self.metadata().name
+    }
+
+    fn supports(&self, choices: &[Choice]) -> bool {
+        !self.metadata().single_byte_keys || choices.iter().all(|c| c.key.len() == 1)
+    }
+}
+
+pub type LatestVotesFn = fn(&[u8], &[Choice], usize) -> Result<ShardedLatestVotes>;
+
+pub struct FnStrategy {
+    pub metadata: StrategyMetadata,
+    pub count_fn: fn(&[u8], &[Choice]) -> Result<Vec<VoteCount>>,
+    pub latest_votes_fn: Option<LatestVotesFn>,
+}
+
+impl CountingStrategy for FnStrategy {
+    fn metadata(&self) -> &StrategyMetadata {
+        &self.metadata
+    }
+
+    fn count_votes(&self, data: &[u8], choices: &[Choice]) -> Result<Vec<VoteCount>> {
+        (self.count_fn)(data, choices)
+    }
+
+    fn latest_votes(
+        &self,
+        data: &[u8],
+        choices: &[Choice],
+        n_shards: usize,
+    ) -> Result<ShardedLatestVotes> {
+        let latest_votes_fn = self
+            .latest_votes_fn
+            .ok_or_else(|| AppError::InternalError {
+                title: "Counting error".to_string(),
+                message: format!("{} can't make the latest votes", self.name()),
+            })?;
+        latest_votes_fn(data, choices, n_shards)
+    }
+
+    fn makes_latest_votes(&self) -> bool {
+        self.latest_votes_fn.is_some()
+    }
+}
+
+pub const DEFAULT_STRATEGY: &str = "count_votes_35";
+
+const COMMA_SEPARATED: StrategyMetadata = StrategyMetadata {
+    name: "",
+    record_format: RecordFormat::CommaSeparated,
+    latest_vote: LatestVote::ByLedgerOrder,
+    uses_choices: true,
+    single_byte_keys: false,
+    multi_threaded: false,
+};
+
+const FIXED_OFFSETS: StrategyMetadata = StrategyMetadata {
+    record_format: RecordFormat::FixedOffsets,
+    ..COMMA_SEPARATED
+};
+
+const FIXED_WIDTH: StrategyMetadata = StrategyMetadata {
+    record_format: RecordFormat::FixedWidth,
+    single_byte_keys: true,
+    ..COMMA_SEPARATED
+};
+
+pub static STRATEGIES: &[FnStrategy] = &[
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_01",
+            latest_vote: LatestVote::ByTimestamp,
+            uses_choices: false,
+            ..COMMA_SEPARATED
+        },
+        count_fn: |data, _| count_votes_01(data),
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_03",
+            uses_choices: false,
+            ..COMMA_SEPARATED
+        },
+        count_fn: |data, _| count_votes_03(data),
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_04",
+            uses_choices: false,
+            ..COMMA_SEPARATED
+        },
+        count_fn: |data, _| count_votes_04(data),
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_06",
+            uses_choices: false,
+            ..COMMA_SEPARATED
+        },
+        count_fn: |data, _| count_votes_06(data),
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_08",
+            uses_choices: false,
+            ..COMMA_SEPARATED
+        },
+        count_fn: |data, _| count_votes_08(data),
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_10",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_10,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_11",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_11,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_12",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_12,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_13",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_13,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_14",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_14,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_15",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_15,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_16",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_16,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_18",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_18,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_19",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_19,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_20",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_20,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_22",
+            ..COMMA_SEPARATED
+        },
+        count_fn: count_votes_22,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_23",
+            ..FIXED_OFFSETS
+        },
+        count_fn: count_votes_23,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_24",
+            ..FIXED_OFFSETS
+        },
+        count_fn: count_votes_24,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_25",
+            ..FIXED_OFFSETS
+        },
+        count_fn: count_votes_25,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_26",
+            ..FIXED_OFFSETS
+        },
+        count_fn: count_votes_26,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_27",
+            ..FIXED_OFFSETS
+        },
+        count_fn: count_votes_27,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_28",
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_28,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_29",
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_29,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_30",
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_30,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_31",
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_31,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_34",
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_34,
+        latest_votes_fn: None,
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_35",
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_35,
+        latest_votes_fn: Some(latest_votes_35),
+    },
+    FnStrategy {
+        metadata: StrategyMetadata {
+            name: "count_votes_36",
+            multi_threaded: true,
+            ..FIXED_WIDTH
+        },
+        count_fn: count_votes_36,
+        latest_votes_fn: Some(latest_votes_36),
+    },
+];
+
+pub fn find_strategy(name: &str) -> Option<&'static dyn CountingStrategy> {
+    STRATEGIES
+        .iter()
+        .find(|strategy| strategy.name() == name)
+        .map(|strategy| strategy as &dyn CountingStrategy)
+}
+
+pub fn strategy_names() -> Vec<&'static str> {
+    STRATEGIES.iter().map(|strategy| strategy.name()).collect()
+}
+
+pub fn latest_votes_strategy_names() -> Vec<&'static str> {
+    STRATEGIES
+        .iter()
+        .filter(|strategy| strategy.makes_latest_votes())
+        .map(|strategy| strategy.name())
+        .collect()
+}
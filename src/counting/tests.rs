@@ -3,62 +3,188 @@
 // Adjust the `use` paths if the functions are located in different modules.
 
 #[cfg(test)]
+// Nested in `counting::tests` as it was first written
+#[allow(clippy::module_inception)]
 mod tests {
     use crate::counting::counting_funcs::*; // Import all items from the parent module.
-    use crate::models::VoteCount;
-    use crate::ledgers::load_cl;
+    use crate::counting::strategies::{CountingStrategy, STRATEGIES};
+    use crate::counting::utils::{
+        counts_from_latest_votes, make_choices_lookup, make_latest_votes_hashmap,
+        make_sharded_latest_votes,
+    };
     use crate::errors::Result;
+    use crate::ledgers::load_cl;
+    use crate::models::VoteCount;
     use crate::utils::load_voting_config;
+    use rustc_hash::FxHashMap;
+
 
+    fn sorted(vote_counts: Vec<VoteCount>) -> Vec<VoteCount> {
+        let mut sorted_counts = vote_counts;
+        sorted_counts.sort_by(|a, b| a.choice.cmp(&b.choice));
+        sorted_counts
+    }
 
     #[test]
     fn test_all_count_votes_functions_return_same_value() -> Result<()> {
         // Load choices
-        let config = load_voting_config("examples/voting_config_012.json");
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
         let choices = config.choices;
         let data = load_cl("examples/cl_10.csv").unwrap();
 
-        fn sorted(vote_counts: Vec<VoteCount>) -> Vec<VoteCount> {
-            let mut sorted_counts = vote_counts.clone();
-            sorted_counts.sort_by(|a, b| a.choice.cmp(&b.choice));
-            sorted_counts
+        let reference_counts = sorted(count_votes_35(&data, &choices)?);
+
+        for strategy in STRATEGIES {
+            assert!(strategy.supports(&choices), "{}", strategy.name());
+            assert_eq!(
+                sorted(strategy.count_votes(&data, &choices)?),
+                reference_counts,
+                "{}",
+                strategy.name()
+            );
         }
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_votes_36_with_any_number_of_threads() -> Result<()> {
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+        let choices = config.choices;
+        let data = load_cl("examples/cl_10.csv")?;
         let reference_counts = sorted(count_votes_35(&data, &choices)?);
 
-        assert_eq!(sorted(count_votes_01(&data)?), reference_counts);
-        assert_eq!(sorted(count_votes_03(&data)?), reference_counts);
-        assert_eq!(sorted(count_votes_04(&data)?), reference_counts);
-        assert_eq!(sorted(count_votes_06(&data)?), reference_counts);
-        assert_eq!(sorted(count_votes_08(&data)?), reference_counts);
-        assert_eq!(sorted(count_votes_10(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_11(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_12(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_13(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_14(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_15(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_16(&data, &choices)?), reference_counts);
-        // assert_eq!(sorted(count_votes_17(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_18(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_19(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_20(&data, &choices)?), reference_counts);
-        // assert_eq!(sorted(count_votes_21(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_22(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_23(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_24(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_25(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_26(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_27(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_28(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_29(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_30(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_31(&data, &choices)?), reference_counts);
-        // assert_eq!(sorted(count_votes_32(&data, &choices)?), reference_counts);
-        // assert_eq!(sorted(count_votes_33(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_34(&data, &choices)?), reference_counts);
-        assert_eq!(sorted(count_votes_35(&data, &choices)?), reference_counts);
-
-        return Ok(());
+        for n_threads in 1..=4 {
+            assert_eq!(
+                sorted(count_votes_36_with_threads(&data, &choices, n_threads)?),
+                reference_counts,
+                "{} threads",
+                n_threads
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sharded_latest_votes_match_unsharded() -> Result<()> {
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+        let data = load_cl("examples/cl_10.csv")?;
+        let choice_to_idx = make_choices_lookup(&config.choices);
+
+        let unsharded = make_latest_votes_hashmap(&data, choice_to_idx.clone(), 0, 1);
+
+        for n_shards in 2..=4 {
+            let mut merged = FxHashMap::default();
+            for shard in 0..n_shards {
+                let latest_votes =
+                    make_latest_votes_hashmap(&data, choice_to_idx.clone(), shard, n_shards);
+                for (user_id_hash, choice_idx) in latest_votes {
+                    // Each voter must belong to exactly one shard
+                    assert!(merged.insert(user_id_hash, choice_idx).is_none());
+                }
+            }
+            assert_eq!(merged, unsharded);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sharded_latest_votes_in_one_pass_match_per_shard() -> Result<()> {
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+        let data = load_cl("examples/cl_10.csv")?;
+        let choice_to_idx = make_choices_lookup(&config.choices);
+
+        for n_shards in 1..=4 {
+            let sharded = make_sharded_latest_votes(&data, &choice_to_idx, n_shards);
+            assert_eq!(sharded.len(), n_shards);
+            for (shard, latest_votes) in sharded.iter().enumerate() {
+                let expected =
+                    make_latest_votes_hashmap(&data, choice_to_idx.clone(), shard, n_shards);
+                assert_eq!(*latest_votes, expected);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_byte_key_strategies_reject_longer_keys() {
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+        let mut choices = config.choices;
+        choices[0].key = "Alice".to_string();
+
+        for strategy in STRATEGIES {
+            assert_eq!(
+                strategy.supports(&choices),
+                !strategy.metadata().single_byte_keys,
+                "{}",
+                strategy.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_choices_and_malformed_records_are_invalid() -> Result<()> {
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+        let choices = config.choices;
+        let mut data = load_cl("examples/cl_10.csv")?;
+        // Voter changes to a choice that was removed from the config
+        data.extend_from_slice(b"feSpnPMgK_DhLhVh,1730291337380,Z\n");
+        // Voter whose latest record is corrupt
+        data.extend_from_slice(b"OBKyI_VRo43Zel8N;1730291337381,A\n");
+
+        let latest_votes = make_latest_votes_hashmap(&data, make_choices_lookup(&choices), 0, 1);
+        let (counts, invalid) = counts_from_latest_votes(&latest_votes, &choices);
+
+        assert_eq!(counts, vec![2, 1, 1]);
+        assert_eq!(invalid, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strategies_make_the_same_latest_votes() -> Result<()> {
+        let config = load_voting_config("examples/voting_config_ABC.json").unwrap();
+        let choices = config.choices;
+        let mut data = load_cl("examples/cl_10.csv")?;
+        data.extend_from_slice(b"feSpnPMgK_DhLhVh,1730291337380,Z\n");
+        data.extend_from_slice(b"OBKyI_VRo43Zel8N;1730291337381,A\n");
+        let choice_to_idx = make_choices_lookup(&choices);
+
+        for strategy in STRATEGIES {
+            if !strategy.makes_latest_votes() {
+                assert!(strategy.latest_votes(&data, &choices, 1).is_err());
+                continue;
+            }
+
+            for n_shards in 1..=4 {
+                let sharded = strategy.latest_votes(&data, &choices, n_shards)?;
+                assert_eq!(
+                    sharded,
+                    make_sharded_latest_votes(&data, &choice_to_idx, n_shards),
+                    "{} with {} shards",
+                    strategy.name(),
+                    n_shards
+                );
+
+                // What the Counts Workers start from adds up to the counts
+                let mut counts = vec![0; choices.len()];
+                let mut invalid = 0;
+                for latest_votes in &sharded {
+                    let (shard_counts, shard_invalid) =
+                        counts_from_latest_votes(latest_votes, &choices);
+                    for (total, count) in counts.iter_mut().zip(shard_counts) {
+                        *total += count;
+                    }
+                    invalid += shard_invalid;
+                }
+                assert_eq!(counts, vec![2, 1, 1], "{}", strategy.name());
+                assert_eq!(invalid, 2, "{}", strategy.name());
+            }
+        }
 
+        Ok(())
     }
-}
\ No newline at end of file
+}
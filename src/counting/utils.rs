@@ -1,9 +1,19 @@
 use crate::models::{Choice, VoteCount};
-use log::info;
 use rustc_hash::{FxHashMap, FxHashSet};
 use std::collections::hash_map::Entry;
+use tracing::{info, warn};
 
-const RECORD_SIZE: usize = 33;
+// CL records are `user_id_hash,timestamp,choice\n`: a 16 byte hash, a 13
+// digit millisecond timestamp and a single byte choice
+pub const RECORD_SIZE: usize = 33;
+pub const HASH_SIZE: usize = 16;
+
+// Choice index for voters whose latest CL record has a choice that isn't in
+// the config, or is malformed. These votes go in the invalid bucket.
+pub const INVALID_CHOICE_IDX: usize = usize::MAX;
+
+// The latest vote of each voter as a choice index, one map per shard
+pub type ShardedLatestVotes = Vec<FxHashMap<u128, usize>>;
 
 pub fn make_choices_lookup(choices: &[Choice]) -> FxHashMap<u8, usize> {
     let mut choice_to_index: FxHashMap<u8, usize> =
@@ -21,43 +31,141 @@ pub fn user_id_hash_u128_from_bytes(bytes: &[u8]) -> u128 {
     u128::from_le_bytes(bytes.try_into().expect("Invalid user_id_hash length"))
 }
 
+pub fn voter_partition(user_id_hash: u128, n_partitions: usize) -> usize {
+    // The hash bytes are base64 text, so fold and mix them before taking the
+    // modulus to spread voters evenly across partitions
+    let folded = (user_id_hash as u64) ^ ((user_id_hash >> 64) as u64);
+    (folded.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize % n_partitions
+}
+
 pub fn make_latest_votes_hashmap(
     data: &[u8],
     choice_to_idx: FxHashMap<u8, usize>,
+    shard: usize,
+    n_shards: usize,
 ) -> FxHashMap<u128, usize> {
-    let mut latest_votes = init_latest_votes_hashmap(data);
+    // Only voters in `shard` are kept; pass 0 and 1 to keep every voter
+    let mut latest_votes = init_latest_votes_hashmap(data, n_shards);
+    let mut invalid_records = InvalidRecords::default();
 
     for line in data.rchunks_exact(RECORD_SIZE) {
         let user_id_hash = user_id_hash_u128_from_bytes(&line[..16]);
+        if voter_partition(user_id_hash, n_shards) != shard {
+            continue;
+        }
 
         match latest_votes.entry(user_id_hash) {
             Entry::Occupied(_) => {
                 continue; // not the users latest vote
             }
             Entry::Vacant(v) => {
-                let choice = line[31];
-                let choice_idx = choice_to_idx.get(&choice).unwrap();
-                v.insert(*choice_idx);
+                v.insert(invalid_records.choice_idx(line, &choice_to_idx));
             }
         }
     }
 
+    invalid_records.warn(data);
     info!("made latest_votes. size: {}", latest_votes.len());
     latest_votes
 }
 
+pub fn make_sharded_latest_votes(
+    data: &[u8],
+    choice_to_idx: &FxHashMap<u8, usize>,
+    n_shards: usize,
+) -> ShardedLatestVotes {
+    // The latest votes of every shard from a single pass over the CL, for
+    // when all the shards are built at once
+    let mut shards: ShardedLatestVotes = (0..n_shards)
+        .map(|_| init_latest_votes_hashmap(data, n_shards))
+        .collect();
+    let mut invalid_records = InvalidRecords::default();
+
+    for line in data.rchunks_exact(RECORD_SIZE) {
+        let user_id_hash = user_id_hash_u128_from_bytes(&line[..16]);
+        let latest_votes = &mut shards[voter_partition(user_id_hash, n_shards)];
+        if let Entry::Vacant(v) = latest_votes.entry(user_id_hash) {
+            v.insert(invalid_records.choice_idx(line, choice_to_idx));
+        }
+    }
+
+    invalid_records.warn(data);
+    info!(
+        "made latest_votes. sizes: {:?}",
+        shards
+            .iter()
+            .map(|latest_votes| latest_votes.len())
+            .collect::<Vec<_>>()
+    );
+    shards
+}
+
+// Tallies the latest votes that are counted as invalid while the latest
+// votes are made
+#[derive(Default)]
+pub struct InvalidRecords {
+    unknown_choices: usize,
+    malformed_records: usize,
+}
+
+impl InvalidRecords {
+    pub fn choice_idx(&mut self, line: &[u8], choice_to_idx: &FxHashMap<u8, usize>) -> usize {
+        if !is_well_formed_record(line) {
+            self.malformed_records += 1;
+            INVALID_CHOICE_IDX
+        } else if let Some(&choice_idx) = choice_to_idx.get(&line[31]) {
+            choice_idx
+        } else {
+            self.unknown_choices += 1;
+            INVALID_CHOICE_IDX
+        }
+    }
+
+    pub fn add(&mut self, other: &InvalidRecords) {
+        self.unknown_choices += other.unknown_choices;
+        self.malformed_records += other.malformed_records;
+    }
+
+    pub fn warn(&self, data: &[u8]) {
+        if !data.len().is_multiple_of(RECORD_SIZE) {
+            warn!(
+                "CL size {} is not a multiple of {} bytes; the first {} bytes were skipped",
+                data.len(),
+                RECORD_SIZE,
+                data.len() % RECORD_SIZE
+            );
+        }
+        if self.unknown_choices > 0 || self.malformed_records > 0 {
+            warn!(
+                "counted as invalid: {} latest votes with unknown choices, {} malformed records",
+                self.unknown_choices, self.malformed_records
+            );
+        }
+    }
+}
+
+pub fn is_well_formed_record(line: &[u8]) -> bool {
+    // user_id_hash,timestamp,choice\n
+    line[16] == b',' && line[30] == b',' && line[32] == b'\n'
+}
+
 pub fn counts_from_latest_votes(
     latest_votes: &FxHashMap<u128, usize>,
-    choices: &Vec<Choice>,
-) -> Vec<u32> {
+    choices: &[Choice],
+) -> (Vec<u32>, u32) {
+    // Returns the count for each choice and the number of invalid votes
     let mut counts = vec![0u32; choices.len()];
+    let mut invalid = 0;
 
     for &choice_idx in latest_votes.values() {
-        counts[choice_idx] += 1;
+        match counts.get_mut(choice_idx) {
+            Some(count) => *count += 1,
+            None => invalid += 1,
+        }
     }
 
-    info!("made counts: {:?}", counts);
-    counts
+    info!("made counts: {:?}, invalid: {}", counts, invalid);
+    (counts, invalid)
 }
 
 pub fn init_seen_hashset(data: &[u8]) -> FxHashSet<u128> {
@@ -67,8 +175,8 @@ pub fn init_seen_hashset(data: &[u8]) -> FxHashSet<u128> {
     seen
 }
 
-pub fn init_latest_votes_hashmap(data: &[u8]) -> FxHashMap<u128, usize> {
-    let max_n_lines = data.len() / RECORD_SIZE + 1;
+pub fn init_latest_votes_hashmap(data: &[u8], n_shards: usize) -> FxHashMap<u128, usize> {
+    let max_n_lines = data.len() / RECORD_SIZE / n_shards + 1;
     let latest_votes: FxHashMap<u128, usize> =
         FxHashMap::with_capacity_and_hasher(max_n_lines, Default::default());
     latest_votes
@@ -0,0 +1,500 @@
+// Anonymous eligibility credentials using RSA blind signatures.
+//
+// An authenticated voter picks a random message, blinds it and has the
+// backend sign the blinded value. Unblinding gives a valid signature on a
+// message the backend has never seen, so the (message, signature) pair
+// proves eligibility without revealing who it was issued to. Votes cast
+// with it are recorded under a nullifier derived from the message instead
+// of the voter's user_id_hash, so revoting still replaces the earlier vote.
+
+use crate::errors::{AppError, ErrorCode, Result};
+use crate::peppers::Peppers;
+use actix_web::web;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use blake2::{digest::consts::U12, Blake2b, Blake2b512, Digest};
+use num_bigint_dig::{ModInverse, RandBigInt};
+use rand::rngs::OsRng;
+use rsa::hazmat::rsa_decrypt_and_check;
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+pub const CREDENTIAL_MESSAGE_LEN: usize = 32;
+const MIN_KEY_BITS: usize = 2048;
+
+// Serialized form of the public key, served to clients for blinding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialPublicKey {
+    // Big-endian base64url
+    pub n: String,
+    pub e: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credential {
+    // CREDENTIAL_MESSAGE_LEN random bytes chosen by the voter, base64url
+    pub message: String,
+    // Unblinded signature over the message, base64url
+    pub signature: String,
+}
+
+// What a voter keeps between blinding and finalizing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlindingState {
+    pub message: String,
+    pub blinded_message: String,
+    // Inverse of the blinding factor; must stay secret
+    pub unblinder: String,
+}
+
+#[derive(Clone)]
+pub struct CredentialIssuer {
+    private_key: RsaPrivateKey,
+    // Voters that have been issued a credential, so each gets only one
+    pub issued: IssuedCredentials,
+}
+
+impl CredentialIssuer {
+    pub fn new(private_key: RsaPrivateKey, issued: IssuedCredentials) -> Result<Self> {
+        if private_key.size() * 8 < MIN_KEY_BITS {
+            return Err(AppError::InternalError {
+                title: "Credential key too small".to_string(),
+                message: format!("Credential key must be at least {} bits", MIN_KEY_BITS),
+            });
+        }
+        Ok(Self {
+            private_key,
+            issued,
+        })
+    }
+
+    pub fn public_key(&self) -> CredentialPublicKey {
+        CredentialPublicKey::from(&self.private_key.to_public_key())
+    }
+
+    // Signs a blinded message for a voter, at most once per voter. The
+    // issuance is held while signing and only returned once it's synced to
+    // the issued log, so a failed signature doesn't use it up and a restart
+    // can't issue a second one. A voter whose response was lost can retry
+    // with the same blinded message; the signature is deterministic, so
+    // signing it again doesn't issue a second credential.
+    pub async fn issue(&self, user_id_hash: &str, blinded_message: &str) -> Result<String> {
+        let blinded = decode_biguint(blinded_message, "blinded_message")?;
+        if &blinded >= self.private_key.n() {
+            return Err(bad_credential("blinded_message is out of range"));
+        }
+
+        let issuance = self
+            .issued
+            .reserve(user_id_hash, &blinded_digest(&blinded))?;
+
+        let blind_signature = rsa_decrypt_and_check(&self.private_key, Some(&mut OsRng), &blinded)
+            .map_err(|err| AppError::InternalError {
+                title: "Blind signing failed".to_string(),
+                message: err.to_string(),
+            })?;
+        if let Some(issuance) = issuance {
+            issuance.commit().await?;
+        }
+        Ok(encode_biguint(&blind_signature, self.private_key.size()))
+    }
+
+    // Checks the credential's signature and returns its nullifier
+    pub fn verify(&self, credential: &Credential, peppers: &Peppers) -> Result<String> {
+        let message = URL_SAFE_NO_PAD
+            .decode(&credential.message)
+            .map_err(|_| bad_credential("message must be base64url"))?;
+        if message.len() != CREDENTIAL_MESSAGE_LEN {
+            return Err(bad_credential("message has the wrong length"));
+        }
+        let signature = decode_biguint(&credential.signature, "signature")?;
+
+        let public_key = self.private_key.to_public_key();
+        if &signature >= public_key.n()
+            || signature.modpow(public_key.e(), public_key.n())
+                != message_digest(&public_key, &message)
+        {
+            return Err(AppError::AuthError {
+                code: ErrorCode::InvalidCredential,
+                message: "Invalid credential".to_string(),
+            });
+        }
+
+        let base_nullifier = nullifier(&message, peppers.base_secret());
+        peppers.upgrade(&base_nullifier, peppers.first_version())
+    }
+}
+
+enum Issuance {
+    // Being signed and logged by a request in progress
+    Pending,
+    Issued(String),
+}
+
+// Voters (by user_id_hash) that have been issued a credential, each with a
+// digest of the blinded message signed for them. Every issuance is appended
+// to a log as a `user_id_hash,blinded_digest` line and synced.
+#[derive(Clone)]
+pub struct IssuedCredentials {
+    issued: Arc<Mutex<FxHashMap<String, Issuance>>>,
+    log: Arc<Mutex<File>>,
+}
+
+impl IssuedCredentials {
+    pub fn open(filepath: &str) -> Result<Self> {
+        let mut issued = FxHashMap::default();
+        let contents = match fs::read_to_string(filepath) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        for line in contents.lines() {
+            let Some((user_id_hash, digest)) = line.split_once(',') else {
+                warn!(
+                    "Skipping malformed line in issued credentials log {}: {:?}",
+                    filepath, line
+                );
+                continue;
+            };
+            issued.insert(
+                user_id_hash.to_string(),
+                Issuance::Issued(digest.to_string()),
+            );
+        }
+        info!(
+            "Loaded {} issued credentials from {}",
+            issued.len(),
+            filepath
+        );
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filepath)?;
+        Ok(Self {
+            issued: Arc::new(Mutex::new(issued)),
+            log: Arc::new(Mutex::new(log)),
+        })
+    }
+
+    // Holds the voter's issuance for this blinded message. Returns None when
+    // it has already been issued for the same blinded message, and refuses
+    // any other blinded message once the voter has one.
+    fn reserve(&self, user_id_hash: &str, digest: &str) -> Result<Option<IssuanceReservation>> {
+        let mut issued = self
+            .issued
+            .lock()
+            .expect("Issued credentials lock poisoned");
+        match issued.get(user_id_hash) {
+            Some(Issuance::Issued(issued_digest)) if issued_digest == digest => Ok(None),
+            Some(_) => Err(AppError::Conflict {
+                code: ErrorCode::CredentialAlreadyIssued,
+                message: "A credential has already been issued".to_string(),
+            }),
+            None => {
+                issued.insert(user_id_hash.to_string(), Issuance::Pending);
+                Ok(Some(IssuanceReservation {
+                    issued: self.clone(),
+                    user_id_hash: user_id_hash.to_string(),
+                    digest: digest.to_string(),
+                    committed: false,
+                }))
+            }
+        }
+    }
+
+    // Blocks on the sync, so it's run on the blocking thread pool
+    fn append(&self, line: &str) -> Result<()> {
+        let mut log = self
+            .log
+            .lock()
+            .expect("Issued credentials log lock poisoned");
+        log.write_all(line.as_bytes())?;
+        log.sync_data()?;
+        Ok(())
+    }
+}
+
+// A voter's issuance held by a request in progress. Dropping it without
+// committing lets the voter ask again.
+struct IssuanceReservation {
+    issued: IssuedCredentials,
+    user_id_hash: String,
+    digest: String,
+    committed: bool,
+}
+
+impl IssuanceReservation {
+    // Writes the issuance to the log and syncs it, off the async worker
+    // thread
+    async fn commit(mut self) -> Result<()> {
+        let issued = self.issued.clone();
+        let line = format!("{},{}\n", self.user_id_hash, self.digest);
+        web::block(move || issued.append(&line))
+            .await
+            .map_err(|err| AppError::InternalError {
+                title: "Credential issuance logging failed".to_string(),
+                message: err.to_string(),
+            })??;
+
+        let mut issued = self
+            .issued
+            .issued
+            .lock()
+            .expect("Issued credentials lock poisoned");
+        issued.insert(
+            self.user_id_hash.clone(),
+            Issuance::Issued(std::mem::take(&mut self.digest)),
+        );
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for IssuanceReservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            let mut issued = self
+                .issued
+                .issued
+                .lock()
+                .expect("Issued credentials lock poisoned");
+            issued.remove(&self.user_id_hash);
+        }
+    }
+}
+
+impl From<&RsaPublicKey> for CredentialPublicKey {
+    fn from(public_key: &RsaPublicKey) -> Self {
+        Self {
+            n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+        }
+    }
+}
+
+impl TryFrom<&CredentialPublicKey> for RsaPublicKey {
+    type Error = AppError;
+
+    fn try_from(key: &CredentialPublicKey) -> Result<Self> {
+        let n = decode_biguint(&key.n, "n")?;
+        let e = decode_biguint(&key.e, "e")?;
+        RsaPublicKey::new(n, e).map_err(|err| bad_credential(&err.to_string()))
+    }
+}
+
+// Client side: blinds a fresh random message
+pub fn blind(public_key: &RsaPublicKey) -> BlindingState {
+    let mut message = [0u8; CREDENTIAL_MESSAGE_LEN];
+    rand::RngCore::fill_bytes(&mut OsRng, &mut message);
+
+    let n = public_key.n();
+    let (r, unblinder) = loop {
+        let r = OsRng.gen_biguint_below(n);
+        if let Some(inverse) = (&r).mod_inverse(n).and_then(|inv| inv.to_biguint()) {
+            break (r, inverse);
+        }
+    };
+    let blinded = (message_digest(public_key, &message) * r.modpow(public_key.e(), n)) % n;
+
+    BlindingState {
+        message: URL_SAFE_NO_PAD.encode(message),
+        blinded_message: encode_biguint(&blinded, public_key.size()),
+        unblinder: encode_biguint(&unblinder, public_key.size()),
+    }
+}
+
+// Client side: unblinds the backend's signature into a credential
+pub fn finalize(
+    public_key: &RsaPublicKey,
+    state: &BlindingState,
+    blind_signature: &str,
+) -> Result<Credential> {
+    let blind_signature = decode_biguint(blind_signature, "blind_signature")?;
+    let unblinder = decode_biguint(&state.unblinder, "unblinder")?;
+    let signature = (blind_signature * unblinder) % public_key.n();
+
+    Ok(Credential {
+        message: state.message.clone(),
+        signature: encode_biguint(&signature, public_key.size()),
+    })
+}
+
+// Full-domain hash of the message, expanded with Blake2b-512 to one byte
+// less than the modulus so it's always below n
+fn message_digest(public_key: &RsaPublicKey, message: &[u8]) -> BigUint {
+    let len = public_key.size() - 1;
+    let mut digest = Vec::with_capacity(len + 64);
+    let mut counter: u32 = 0;
+    while digest.len() < len {
+        let mut hasher = Blake2b512::new();
+        hasher.update(b"voterium-credential-v1");
+        hasher.update(counter.to_be_bytes());
+        hasher.update(message);
+        digest.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    digest.truncate(len);
+    BigUint::from_bytes_be(&digest)
+}
+
+// Identifies the blinded message signed for a voter in the issued log
+fn blinded_digest(blinded: &BigUint) -> String {
+    let mut hasher = Blake2b::<U12>::new();
+    hasher.update(b"voterium-blinded-v1");
+    hasher.update(blinded.to_bytes_be());
+    URL_SAFE_NO_PAD.encode(hasher.finalize())
+}
+
+// Same size and encoding as hash_user_id, so nullifiers fit the CL records
+// and are upgraded with later peppers the same way
+fn nullifier(message: &[u8], backend_salt: &[u8]) -> String {
+    let mut hasher = Blake2b::<U12>::new();
+    hasher.update(b"voterium-nullifier-v1");
+    hasher.update(message);
+    hasher.update(backend_salt);
+    URL_SAFE_NO_PAD.encode(hasher.finalize())
+}
+
+fn encode_biguint(value: &BigUint, len: usize) -> String {
+    let bytes = value.to_bytes_be();
+    let mut padded = vec![0u8; len.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    URL_SAFE_NO_PAD.encode(padded)
+}
+
+fn decode_biguint(value: &str, name: &str) -> Result<BigUint> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|_| bad_credential(&format!("{} must be base64url", name)))?;
+    Ok(BigUint::from_bytes_be(&bytes))
+}
+
+fn bad_credential(message: &str) -> AppError {
+    AppError::BadRequest {
+        code: ErrorCode::InvalidCredential,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{peppers, temp_filepath};
+    use std::sync::OnceLock;
+
+    // Generating a key is slow, so the tests share one
+    fn private_key() -> RsaPrivateKey {
+        static PRIVATE_KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        PRIVATE_KEY
+            .get_or_init(|| RsaPrivateKey::new(&mut OsRng, MIN_KEY_BITS).unwrap())
+            .clone()
+    }
+
+    fn issued_filepath(name: &str) -> String {
+        temp_filepath("credentials", name, "log")
+    }
+
+    fn open_issuer(issued_filepath: &str) -> Result<CredentialIssuer> {
+        CredentialIssuer::new(private_key(), IssuedCredentials::open(issued_filepath)?)
+    }
+
+    fn is_already_issued(result: Result<String>) -> bool {
+        matches!(
+            result,
+            Err(AppError::Conflict {
+                code: ErrorCode::CredentialAlreadyIssued,
+                ..
+            })
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_blind_sign_finalize_verify() -> Result<()> {
+        let issuer = open_issuer(&issued_filepath("roundtrip"))?;
+        // As a client would, from the served key
+        let public_key = RsaPublicKey::try_from(&issuer.public_key())?;
+
+        let state = blind(&public_key);
+        let blind_signature = issuer.issue("voter-1", &state.blinded_message).await?;
+        let credential = finalize(&public_key, &state, &blind_signature)?;
+
+        let nullifier = issuer.verify(&credential, &peppers())?;
+        assert_eq!(nullifier.len(), 16);
+        // Revotes with the credential land on the same nullifier
+        assert_eq!(issuer.verify(&credential, &peppers())?, nullifier);
+
+        // The signature only covers the voter's own message
+        let other = blind(&public_key);
+        let forged = Credential {
+            message: other.message,
+            signature: credential.signature.clone(),
+        };
+        assert!(issuer.verify(&forged, &peppers()).is_err());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_second_credential_is_refused() -> Result<()> {
+        let filepath = issued_filepath("second");
+        let issuer = open_issuer(&filepath)?;
+        let public_key = issuer.private_key.to_public_key();
+
+        issuer
+            .issue("voter-1", &blind(&public_key).blinded_message)
+            .await?;
+        assert!(is_already_issued(
+            issuer
+                .issue("voter-1", &blind(&public_key).blinded_message)
+                .await
+        ));
+
+        // The issuance was synced, so a restart doesn't issue another
+        let reopened = open_issuer(&filepath)?;
+        assert!(is_already_issued(
+            reopened
+                .issue("voter-1", &blind(&public_key).blinded_message)
+                .await
+        ));
+        reopened
+            .issue("voter-2", &blind(&public_key).blinded_message)
+            .await?;
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_retry_with_the_same_blinded_message_is_signed_again() -> Result<()> {
+        let filepath = issued_filepath("retry");
+        let issuer = open_issuer(&filepath)?;
+        let public_key = issuer.private_key.to_public_key();
+        let state = blind(&public_key);
+
+        // As if the first response never reached the voter
+        let blind_signature = issuer.issue("voter-1", &state.blinded_message).await?;
+        assert_eq!(
+            issuer.issue("voter-1", &state.blinded_message).await?,
+            blind_signature
+        );
+        let reopened = open_issuer(&filepath)?;
+        assert_eq!(
+            reopened.issue("voter-1", &state.blinded_message).await?,
+            blind_signature
+        );
+
+        // Still one credential: other blinded messages are refused
+        assert!(is_already_issued(
+            reopened
+                .issue("voter-1", &blind(&public_key).blinded_message)
+                .await
+        ));
+        let credential = finalize(&public_key, &state, &blind_signature)?;
+        issuer.verify(&credential, &peppers())?;
+        Ok(())
+    }
+}
@@ -1,73 +1,329 @@
 pub type Result<T> = core::result::Result<T, AppError>;
 
+use actix_web::http::{header::ContentType, StatusCode};
 use actix_web::{HttpResponse, ResponseError};
 use serde::Serialize;
 use thiserror::Error;
+use tracing::{error, warn};
 
-#[derive(Error, Debug, Serialize)]
+// Queues drain and workers restart within seconds
+const SERVICE_UNAVAILABLE_RETRY_AFTER_SECS: u64 = 1;
+
+// Stable, machine-readable codes sent to clients with every error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InternalError,
+    InvalidRequest,
+    InvalidChoice,
+    InvalidCredential,
+    CredentialsDisabled,
+    Unauthorized,
+    TokenUsed,
+    Forbidden,
+    ElectionClosed,
+    TokenVotingDisabled,
+    NotFound,
+    CredentialAlreadyIssued,
+    RateLimited,
+    LedgerUnavailable,
+    CountsUnavailable,
+    Overloaded,
+    ShuttingDown,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::InvalidChoice => "INVALID_CHOICE",
+            ErrorCode::InvalidCredential => "INVALID_CREDENTIAL",
+            ErrorCode::CredentialsDisabled => "CREDENTIALS_DISABLED",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::TokenUsed => "TOKEN_USED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::ElectionClosed => "ELECTION_CLOSED",
+            ErrorCode::TokenVotingDisabled => "TOKEN_VOTING_DISABLED",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::CredentialAlreadyIssued => "CREDENTIAL_ALREADY_ISSUED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::LedgerUnavailable => "LEDGER_UNAVAILABLE",
+            ErrorCode::CountsUnavailable => "COUNTS_UNAVAILABLE",
+            ErrorCode::Overloaded => "OVERLOADED",
+            ErrorCode::ShuttingDown => "SHUTTING_DOWN",
+        }
+    }
+}
+
+// Also used as the `reason` label of rejected votes
+impl Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// Only the message of client errors (4xx) is sent to clients. Server errors
+// are logged with their details and answered with a generic message.
+#[derive(Error, Debug)]
 pub enum AppError {
     #[error("Internal server error - {title}: {message}")]
     InternalError { title: String, message: String },
 
-    #[error("Bad request - {title}: {message}")]
-    BadRequest { title: String, message: String },
+    #[error("Bad request - {code:?}: {message}")]
+    BadRequest { code: ErrorCode, message: String },
+
+    #[error("Authentication error - {code:?}: {message}")]
+    AuthError { code: ErrorCode, message: String },
+
+    #[error("Forbidden - {code:?}: {message}")]
+    Forbidden { code: ErrorCode, message: String },
+
+    #[error("Not found - {code:?}: {message}")]
+    NotFound { code: ErrorCode, message: String },
+
+    #[error("Conflict - {code:?}: {message}")]
+    Conflict { code: ErrorCode, message: String },
 
-    #[error("Authentication error: {message}")]
-    AuthError { message: String },
+    #[error("Too many requests: {message}")]
+    TooManyRequests {
+        message: String,
+        retry_after_secs: u64,
+    },
+
+    #[error("Service unavailable - {code:?}: {message}")]
+    ServiceUnavailable { code: ErrorCode, message: String },
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::InternalError { .. } => ErrorCode::InternalError,
+            AppError::TooManyRequests { .. } => ErrorCode::RateLimited,
+            AppError::BadRequest { code, .. }
+            | AppError::AuthError { code, .. }
+            | AppError::Forbidden { code, .. }
+            | AppError::NotFound { code, .. }
+            | AppError::Conflict { code, .. }
+            | AppError::ServiceUnavailable { code, .. } => *code,
+        }
+    }
+
+    // A worker's channels only close once it has stopped
+    pub fn ledger_worker_stopped() -> AppError {
+        AppError::ServiceUnavailable {
+            code: ErrorCode::LedgerUnavailable,
+            message: "Ledger Worker has stopped".to_string(),
+        }
+    }
+
+    pub fn counts_worker_stopped() -> AppError {
+        AppError::ServiceUnavailable {
+            code: ErrorCode::CountsUnavailable,
+            message: "Counts Worker has stopped".to_string(),
+        }
+    }
+
+    // What the client is told about the error
+    fn detail(&self) -> &str {
+        match self {
+            AppError::InternalError { .. } => "The server failed to handle the request",
+            AppError::ServiceUnavailable { .. } => "The service is temporarily unavailable",
+            AppError::BadRequest { message, .. }
+            | AppError::AuthError { message, .. }
+            | AppError::Forbidden { message, .. }
+            | AppError::NotFound { message, .. }
+            | AppError::Conflict { message, .. }
+            | AppError::TooManyRequests { message, .. } => message,
+        }
+    }
+}
+
+// RFC 7807 problem details
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: &'a str,
+    code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
 }
 
 impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::BadRequest { .. } => StatusCode::BAD_REQUEST,
+            AppError::AuthError { .. } => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            AppError::NotFound { .. } => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
     fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
         match self {
-            AppError::InternalError { .. } => HttpResponse::InternalServerError().json(self),
-            AppError::BadRequest { .. } => HttpResponse::BadRequest().json(self),
-            AppError::AuthError { .. } => HttpResponse::Unauthorized().json(self),
-            // Handle other variants accordingly
+            // Expected under overload, so not worth an error per request
+            AppError::ServiceUnavailable { .. } => warn!("{}", self),
+            _ if status.is_server_error() => error!("{}", self),
+            _ => {}
         }
+
+        let retry_after_secs = match self {
+            AppError::TooManyRequests {
+                retry_after_secs, ..
+            } => Some(*retry_after_secs),
+            AppError::ServiceUnavailable { .. } => Some(SERVICE_UNAVAILABLE_RETRY_AFTER_SECS),
+            _ => None,
+        };
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code(),
+            retry_after_secs,
+        };
+
+        let mut response = HttpResponse::build(status);
+        response.insert_header(ContentType("application/problem+json".parse().unwrap()));
+        if let Some(retry_after_secs) = retry_after_secs {
+            response.insert_header(("Retry-After", retry_after_secs.to_string()));
+        }
+        response.json(problem)
     }
 }
 
 impl From<base64::DecodeError> for AppError {
     fn from(err: base64::DecodeError) -> AppError {
         AppError::BadRequest {
-            title: "Base64 decoding error".to_string(),
+            code: ErrorCode::InvalidRequest,
+            message: format!("Invalid base64: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> AppError {
+        AppError::InternalError {
+            title: "I/O error".to_string(),
             message: err.to_string(),
         }
     }
 }
 
-impl<T> From<tokio::sync::mpsc::error::SendError<T>> for AppError {
-    fn from(err: tokio::sync::mpsc::error::SendError<T>) -> AppError {
+impl From<csv::Error> for AppError {
+    fn from(err: csv::Error) -> AppError {
         AppError::InternalError {
-            title: "Channel send error".to_string(),
+            title: "CSV error".to_string(),
             message: err.to_string(),
         }
     }
 }
 
-impl From<tokio::sync::oneshot::error::RecvError> for AppError {
-    fn from(err: tokio::sync::oneshot::error::RecvError) -> AppError {
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> AppError {
         AppError::InternalError {
-            title: "Oneshot receive error".to_string(),
+            title: "JSON error".to_string(),
             message: err.to_string(),
         }
     }
 }
 
-impl From<std::io::Error> for AppError {
-    fn from(err: std::io::Error) -> AppError {
+impl From<prometheus::Error> for AppError {
+    fn from(err: prometheus::Error) -> AppError {
         AppError::InternalError {
-            title: "I/O error".to_string(),
+            title: "Metrics error".to_string(),
             message: err.to_string(),
         }
     }
 }
 
-impl From<csv::Error> for AppError {
-    fn from(err: csv::Error) -> AppError {
+impl From<rustls::Error> for AppError {
+    fn from(err: rustls::Error) -> AppError {
         AppError::InternalError {
-            title: "CSV error".to_string(),
+            title: "TLS error".to_string(),
             message: err.to_string(),
         }
     }
 }
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(err: jsonwebtoken::errors::Error) -> AppError {
+        AppError::InternalError {
+            title: "JWT error".to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::http::header;
+
+    async fn problem(err: AppError) -> (HttpResponse<()>, serde_json::Value) {
+        let (response, body) = err.error_response().into_parts();
+        let body = to_bytes(body).await.unwrap();
+        (response, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_problem_shape() {
+        let (response, body) = problem(AppError::NotFound {
+            code: ErrorCode::CredentialsDisabled,
+            message: "Anonymous credentials are disabled".to_string(),
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "Anonymous credentials are disabled",
+                "code": "CREDENTIALS_DISABLED",
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_server_errors_hide_their_details() {
+        let (response, body) = problem(AppError::InternalError {
+            title: "I/O error".to_string(),
+            message: "/secret/path: permission denied".to_string(),
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "INTERNAL_ERROR");
+        assert_eq!(body["detail"], "The server failed to handle the request");
+        assert!(body.get("retry_after_secs").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_retryable_errors_say_when() {
+        let (response, body) = problem(AppError::ServiceUnavailable {
+            code: ErrorCode::LedgerUnavailable,
+            message: "Oneshot receive error".to_string(),
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "1");
+        assert_eq!(body["code"], "LEDGER_UNAVAILABLE");
+        assert_eq!(body["retry_after_secs"], 1);
+    }
+}
@@ -1,13 +1,28 @@
-use crate::errors::{AppError, Result};
-use crate::models::{AppState, Ballot, Choice, Claims, CountWorkerBallot, CountWorkerMsg, LedgerWorkerMsg, Vote};
+use crate::auth::{authorize, is_public_route};
+use crate::counting::utils::RECORD_SIZE;
+use crate::credentials::CredentialIssuer;
+use crate::errors::{AppError, ErrorCode, Result};
+use crate::ledgers::cl_records_len;
+use crate::models::{
+    AnonymousVote, AppState, Ballot, Choice, Claims, CountWorkerBallot, CountWorkerMsg,
+    CredentialRequest, LedgerStats, LedgerWorkerMsg, Readiness, Role, Vote, VoteCounts,
+    WorkerHealth,
+};
+use crate::peppers::Peppers;
+use crate::token_uses::TokenUseReservation;
 use crate::utils::gen_random_b64_string;
-use crate::utils::hash_user_id;
-use actix_web::HttpMessage;
+use actix_web::error::JsonPayloadError;
 use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use chrono::Utc;
-use log::info;
-use std::time::Instant;
-// use tokio::sync::oneshot;
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, instrument, Span};
+
+// How long a worker has to answer a readiness ping
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+// Not ready while a worker queue is fuller than this
+const MAX_READY_QUEUE_FILL: f64 = 0.9;
 
 
 #[post("/vote")]
@@ -17,50 +32,138 @@ pub async fn submit_vote(
     req: HttpRequest,
 ) -> Result<HttpResponse> {
     let start_vote = Instant::now();
+    let result = cast_vote(&app_state, &vote, &req).await;
+    app_state.metrics.record_vote(&result, start_vote.elapsed());
+    result
+}
+
+async fn cast_vote(app_state: &AppState, vote: &Vote, req: &HttpRequest) -> Result<HttpResponse> {
     let timestamp = Utc::now().timestamp_millis();
 
-    // Store extensions in a variable to extend its lifetime
-    let extensions = req.extensions();
-    let claims = extensions.get::<Claims>().ok_or(AppError::InternalError {
-        title: "Claims not found".into(),
-        message: "Could not find claims in req.extensions()".into(),
-    })?;
+    let claims = authorize(req, &[Role::Voter])?;
+    verify_voting_open(app_state)?;
+    if app_state.credential_issuer.is_some() {
+        return Err(AppError::Forbidden {
+            code: ErrorCode::TokenVotingDisabled,
+            message: "Votes must be cast with an anonymous credential".to_string(),
+        });
+    }
+
+    verify_valid_choice(vote, &app_state.config.choices)?;
+    app_state.rate_limits.check_ip(req)?;
 
     // Hash the user_id to generate the vote_id receipt
     let start_hash = Instant::now();
-    let user_id = &claims.sub;
-    let user_salt = &claims.salt;
-    let backend_salt = &app_state.backend_salt;
     let vote_id = gen_random_b64_string(12);
 
-    let user_id_hash = hash_user_id(user_id, user_salt, backend_salt)?;
-    let hash_duration = start_hash.elapsed();
+    let user_id_hash = hash_user_id(&app_state.peppers, &claims, false).await?;
+    app_state
+        .metrics
+        .observe_vote_stage("hash", start_hash.elapsed());
+    app_state.rate_limits.check_voter(&user_id_hash)?;
 
-    verify_valid_choice(&vote, &app_state.config.choices)?;
+    // Hold one of the token's votes while the vote is sent. send_ballot uses
+    // it up once the vote is written, even if that's after this request has
+    // timed out; if the vote is never written it's given back.
+    let token_use = match &app_state.token_uses {
+        Some(token_uses) => {
+            let jti = claims.jti.as_deref().ok_or(AppError::AuthError {
+                code: ErrorCode::Unauthorized,
+                message: "Token has no jti".to_string(),
+            })?;
+            let expires_at = claims.exp as i64 + app_state.jwt_config.leeway_secs as i64;
+            Some(token_uses.reserve_use(jti, expires_at)?)
+        }
+        None => None,
+    };
 
     let start_send_msgs = Instant::now();
-    let ballot = Ballot{
+    let ballot = Ballot {
         vote_id: vote_id.clone(),
         user_id_hash,
         timestamp,
-        choice: vote.choice.clone(), 
+        choice: vote.choice.clone(),
     };
 
-    let ledger_sender = &app_state.ledger_channel_sender;
-    let msg = LedgerWorkerMsg::from(&ballot);
-    ledger_sender.send(msg).await?;
+    send_ballot(app_state, &ballot, token_use).await?;
+    app_state
+        .metrics
+        .observe_vote_stage("send", start_send_msgs.elapsed());
+    debug!(vote_id = %vote_id, "Vote accepted");
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "vote_id": vote_id })))
+}
 
-    let count_sender = &app_state.count_channel_sender;
-    let msg = CountWorkerMsg::Vote { ballot: CountWorkerBallot::from(&ballot) };
-    count_sender.send(msg).await?;
 
-    let send_msgs_duration = start_send_msgs.elapsed();
+#[get("/key")]
+pub async fn get_credential_key(app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    let issuer = credential_issuer(&app_state)?;
+    Ok(HttpResponse::Ok().json(issuer.public_key()))
+}
 
-    let total_duration = start_vote.elapsed();
-    info!(
-        "hash user_id: {:?}, send VL CL messages {:?}, /vote: {:?}",
-        hash_duration, send_msgs_duration, total_duration
-    );
+#[post("/issue")]
+pub async fn issue_credential(
+    app_state: web::Data<AppState>,
+    request: web::Json<CredentialRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let claims = authorize(&req, &[Role::Voter])?;
+    let issuer = credential_issuer(&app_state)?;
+
+    // Only used to issue one credential per voter; never written to the CL.
+    // The base hash stays the same when peppers are added.
+    let user_id_hash = hash_user_id(&app_state.peppers, &claims, true).await?;
+    let blind_signature = issuer
+        .issue(&user_id_hash, &request.blinded_message)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "blind_signature": blind_signature })))
+}
+
+#[post("/vote")]
+pub async fn submit_anonymous_vote(
+    app_state: web::Data<AppState>,
+    vote: web::Json<AnonymousVote>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let start_vote = Instant::now();
+    let result = cast_anonymous_vote(&app_state, &vote, &req).await;
+    app_state.metrics.record_vote(&result, start_vote.elapsed());
+    result
+}
+
+async fn cast_anonymous_vote(
+    app_state: &AppState,
+    vote: &AnonymousVote,
+    req: &HttpRequest,
+) -> Result<HttpResponse> {
+    let timestamp = Utc::now().timestamp_millis();
+    let issuer = credential_issuer(app_state)?;
+    verify_voting_open(app_state)?;
+    app_state.rate_limits.check_ip(req)?;
+
+    // The nullifier takes the place of the user_id_hash, so revotes with
+    // the same credential replace the earlier vote
+    let nullifier = issuer.verify(&vote.credential, &app_state.peppers)?;
+    app_state.rate_limits.check_voter(&nullifier)?;
+    let vote = Vote {
+        choice: vote.choice.clone(),
+    };
+    verify_valid_choice(&vote, &app_state.config.choices)?;
+
+    let vote_id = gen_random_b64_string(12);
+    let ballot = Ballot {
+        vote_id: vote_id.clone(),
+        user_id_hash: nullifier,
+        timestamp,
+        choice: vote.choice,
+    };
+    let start_send_msgs = Instant::now();
+    send_ballot(app_state, &ballot, None).await?;
+    app_state
+        .metrics
+        .observe_vote_stage("send", start_send_msgs.elapsed());
+    debug!(vote_id = %vote_id, "Anonymous vote accepted");
 
     Ok(HttpResponse::Ok().json(serde_json::json!({ "vote_id": vote_id })))
 }
@@ -68,12 +171,28 @@ pub async fn submit_vote(
 
 #[get("/results")]
 pub async fn get_results(app_state: web::Data<AppState>) -> Result<HttpResponse> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    app_state.count_channel_sender.send(CountWorkerMsg::GetCounts { resp: tx }).await?;
-    let mut vote_counts = rx.await?;
-    vote_counts.sort_by(|a, b| a.choice.cmp(&b.choice));
+    let vote_counts = current_counts(&app_state).await?;
+    Ok(HttpResponse::Ok().json(vote_counts.counts))
+}
 
-    Ok(HttpResponse::Ok().json(vote_counts))
+#[get("/results/invalid")]
+pub async fn get_invalid_results(app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    // Kept out of /results so its array shape doesn't change
+    let vote_counts = current_counts(&app_state).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "invalid": vote_counts.invalid })))
+}
+
+async fn current_counts(app_state: &AppState) -> Result<VoteCounts> {
+    // Read the published snapshots without messaging the Counts Workers
+    if let Some(vote_counts) = app_state.count_workers.counts() {
+        return Ok(vote_counts);
+    }
+
+    // No snapshots until the initial count is done, so wait on the workers
+    let mut vote_counts = app_state.count_workers.request_counts().await?;
+    vote_counts.counts.sort_by(|a, b| a.choice.cmp(&b.choice));
+
+    Ok(vote_counts)
 }
 
 
@@ -84,6 +203,341 @@ pub async fn get_config(app_state: web::Data<AppState>) -> Result<HttpResponse>
 }
 
 
+#[get("/election")]
+pub async fn get_election(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    authorize(&req, &[Role::Admin])?;
+    let voting_open = app_state.voting_open.load(Ordering::Acquire);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "voting_open": voting_open })))
+}
+
+#[post("/election/open")]
+pub async fn open_election(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let claims = authorize(&req, &[Role::Admin])?;
+    app_state.voting_open.store(true, Ordering::Release);
+    info!("Voting opened by {}", claims.sub);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "voting_open": true })))
+}
+
+#[post("/election/close")]
+pub async fn close_election(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let claims = authorize(&req, &[Role::Admin])?;
+    app_state.voting_open.store(false, Ordering::Release);
+    info!("Voting closed by {}", claims.sub);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "voting_open": false })))
+}
+
+#[get("/stats")]
+pub async fn get_ledger_stats(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    authorize(&req, &[Role::Observer, Role::Admin])?;
+
+    let file_size = |filepath: &str| fs::metadata(filepath).map_or(0, |m| m.len());
+    let cl_bytes = file_size(&app_state.cl_filepath);
+    let cl_records_len = cl_records_len(&app_state.cl_filepath).unwrap_or(0);
+    let ledger_sender = &app_state.ledger_channel_sender;
+
+    let stats = LedgerStats {
+        voting_open: app_state.voting_open.load(Ordering::Acquire),
+        cl_records: cl_records_len / RECORD_SIZE as u64,
+        cl_bytes,
+        vl_bytes: file_size(&app_state.vl_filepath),
+        ledger_queue_depth: ledger_sender.max_capacity() - ledger_sender.capacity(),
+        ledger_queue_capacity: ledger_sender.max_capacity(),
+        count_queue_depths: app_state.count_workers.queue_depths(),
+        count_queue_capacity: app_state.count_workers.shards[0].sender.max_capacity(),
+        queue_rejections: app_state.queue_rejections.load(Ordering::Relaxed),
+        workers_up: app_state.ledger_worker.is_up() && app_state.count_workers.all_up(),
+        worker_restarts: app_state.ledger_worker.restarts() + app_state.count_workers.restarts(),
+        rate_limited: app_state.rate_limits.stats(),
+        counts: app_state.count_workers.counts(),
+    };
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[get("/live")]
+pub async fn live() -> Result<HttpResponse> {
+    // Answering at all means the server is running
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "ok" })))
+}
+
+#[get("/ready")]
+pub async fn ready(app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    let ledger_sender = &app_state.ledger_channel_sender;
+    let ledger_writable = ping_ledger_worker(&app_state).await;
+    let count_pings = app_state.count_workers.ping(PING_TIMEOUT).await;
+
+    let mut workers = vec![WorkerHealth {
+        name: app_state.ledger_worker.name().to_string(),
+        up: app_state.ledger_worker.is_up(),
+        responding: ledger_writable,
+        queue_depth: ledger_sender.max_capacity() - ledger_sender.capacity(),
+        queue_capacity: ledger_sender.max_capacity(),
+    }];
+    workers.extend(app_state.count_workers.shards.iter().zip(count_pings).map(
+        |(shard, responding)| WorkerHealth {
+            name: shard.status.name().to_string(),
+            up: shard.status.is_up(),
+            responding,
+            queue_depth: shard.sender.max_capacity() - shard.sender.capacity(),
+            queue_capacity: shard.sender.max_capacity(),
+        },
+    ));
+
+    let shutting_down = app_state.shutting_down.load(Ordering::Acquire);
+    let config_valid = app_state
+        .config_reloads
+        .iter()
+        .all(|reload| reload.is_valid());
+    let initial_counts_done = app_state.count_workers.counts().is_some();
+    let workers_healthy = workers.iter().all(|worker| {
+        worker.up
+            && worker.responding
+            && (worker.queue_depth as f64) < worker.queue_capacity as f64 * MAX_READY_QUEUE_FILL
+    });
+    let ready = !shutting_down && ledger_writable && initial_counts_done && workers_healthy;
+
+    let readiness = Readiness {
+        ready,
+        shutting_down,
+        config_valid,
+        ledger_writable,
+        initial_counts_done,
+        workers,
+    };
+    if ready {
+        Ok(HttpResponse::Ok().json(readiness))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(readiness))
+    }
+}
+
+#[get("/metrics")]
+pub async fn get_metrics(app_state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    // Observers and admins only, unless PUBLIC_ROUTES makes it public
+    if !is_public_route(&app_state.public_routes, req.method(), req.path()) {
+        authorize(&req, &[Role::Observer, Role::Admin])?;
+    }
+
+    // Gauges that are cheaper to read on scrape than to keep up to date
+    let metrics = &app_state.metrics;
+    let ledger_sender = &app_state.ledger_channel_sender;
+    metrics.set_queue_depth(
+        app_state.ledger_worker.name(),
+        ledger_sender.max_capacity() - ledger_sender.capacity(),
+    );
+    for (shard, depth) in app_state
+        .count_workers
+        .shards
+        .iter()
+        .zip(app_state.count_workers.queue_depths())
+    {
+        metrics.set_queue_depth(shard.status.name(), depth);
+    }
+
+    let file_size = |filepath: &str| fs::metadata(filepath).map_or(0, |m| m.len());
+    let cl_records_len = cl_records_len(&app_state.cl_filepath).unwrap_or(0);
+    metrics.set_ledger_sizes(
+        file_size(&app_state.cl_filepath),
+        file_size(&app_state.vl_filepath),
+        cl_records_len / RECORD_SIZE as u64,
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render()?))
+}
+
+// Unknown routes
+pub async fn not_found(req: HttpRequest) -> Result<HttpResponse> {
+    Err(AppError::NotFound {
+        code: ErrorCode::NotFound,
+        message: format!("No route for {} {}", req.method(), req.path()),
+    })
+}
+
+// Malformed JSON bodies get the same problem response as other errors
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::BadRequest {
+        code: ErrorCode::InvalidRequest,
+        message: err.to_string(),
+    }
+    .into()
+}
+
+// The user_id hash under the current pepper, or only the first one if
+// `base`. Expensive schemes run on the blocking thread pool.
+#[instrument(level = "debug", skip_all)]
+async fn hash_user_id(peppers: &Peppers, claims: &Claims, base: bool) -> Result<String> {
+    let expensive = peppers.scheme().is_expensive();
+    let peppers = peppers.clone();
+    let (user_id, user_salt) = (claims.sub.clone(), claims.salt.clone());
+    let hash = move || {
+        if base {
+            peppers.base_hash_user_id(&user_id, &user_salt)
+        } else {
+            peppers.hash_user_id(&user_id, &user_salt)
+        }
+    };
+
+    if !expensive {
+        return hash();
+    }
+    web::block(hash)
+        .await
+        .map_err(|err| AppError::InternalError {
+            title: "Hashing failed".to_string(),
+            message: err.to_string(),
+        })?
+}
+
+// Sends a ping through the Ledger Worker's queue; true once it has synced
+// the ledgers
+async fn ping_ledger_worker(app_state: &AppState) -> bool {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let ping = async {
+        app_state
+            .ledger_channel_sender
+            .send(LedgerWorkerMsg::ping(tx))
+            .await
+            .ok()?;
+        rx.await.ok()
+    };
+    matches!(
+        tokio::time::timeout(PING_TIMEOUT, ping).await,
+        Ok(Some(true))
+    )
+}
+
+fn verify_voting_open(app_state: &AppState) -> Result<()> {
+    if !app_state.voting_open.load(Ordering::Acquire) {
+        return Err(AppError::Forbidden {
+            code: ErrorCode::ElectionClosed,
+            message: "Voting is closed".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn credential_issuer(app_state: &AppState) -> Result<&CredentialIssuer> {
+    app_state
+        .credential_issuer
+        .as_ref()
+        .ok_or(AppError::NotFound {
+            code: ErrorCode::CredentialsDisabled,
+            message: "Anonymous credentials are disabled".to_string(),
+        })
+}
+
+// Waits up to queue_send_timeout for room in both the Ledger Worker's and
+// the voter's Counts Worker's channel. Both slots are reserved before
+// either message is sent, so a refused vote reaches neither worker. The
+// vote is only counted, its token use only committed, and the caller only
+// answered, once the Ledger Worker has written it. A caller that gives up
+// after ledger_write_timeout gets a 503, but a vote written later is still
+// counted and its token use committed, as it's in the CL.
+#[instrument(level = "debug", skip_all)]
+async fn send_ballot(
+    app_state: &AppState,
+    ballot: &Ballot,
+    token_use: Option<TokenUseReservation>,
+) -> Result<()> {
+    if app_state.shutting_down.load(Ordering::Acquire) {
+        return Err(AppError::ServiceUnavailable {
+            code: ErrorCode::ShuttingDown,
+            message: "Shutting down".to_string(),
+        });
+    }
+    if !app_state.ledger_worker.is_up() {
+        return Err(AppError::ServiceUnavailable {
+            code: ErrorCode::LedgerUnavailable,
+            message: "Ledger Worker is restarting".to_string(),
+        });
+    }
+
+    let ledger_sender = &app_state.ledger_channel_sender;
+    let count_sender = app_state
+        .count_workers
+        .shard_sender(ballot.user_id_hash_u128());
+
+    let reserve = async {
+        let ledger_permit = ledger_sender
+            .reserve()
+            .await
+            .map_err(|_| AppError::ledger_worker_stopped())?;
+        let count_permit = count_sender
+            .clone()
+            .reserve_owned()
+            .await
+            .map_err(|_| AppError::counts_worker_stopped())?;
+        Ok::<_, AppError>((ledger_permit, count_permit))
+    };
+    let Ok(permits) = tokio::time::timeout(app_state.queue_send_timeout, reserve).await else {
+        app_state.queue_rejections.fetch_add(1, Ordering::Relaxed);
+        return Err(AppError::ServiceUnavailable {
+            code: ErrorCode::Overloaded,
+            message: "Worker queues are full".to_string(),
+        });
+    };
+    let (ledger_permit, count_permit) = permits?;
+
+    let (written_tx, mut written_rx) = tokio::sync::oneshot::channel();
+    let mut msg = LedgerWorkerMsg::from(ballot);
+    msg.resp = Some(written_tx);
+    ledger_permit.send(msg);
+    let count_msg = CountWorkerMsg::Vote {
+        ballot: CountWorkerBallot::from(ballot),
+        span: Span::current(),
+    };
+
+    // Dropped unanswered if the write fails
+    match tokio::time::timeout(app_state.ledger_write_timeout, &mut written_rx).await {
+        Ok(written) => {
+            written.map_err(|_| AppError::ServiceUnavailable {
+                code: ErrorCode::LedgerUnavailable,
+                message: "Ledger Worker failed to write the vote".to_string(),
+            })?;
+        }
+        Err(_) => {
+            tokio::spawn(async move {
+                if written_rx.await.is_ok() {
+                    count_permit.send(count_msg);
+                    commit_token_use(token_use).await;
+                }
+            });
+            return Err(AppError::ServiceUnavailable {
+                code: ErrorCode::LedgerUnavailable,
+                message: "Timed out waiting for the ledgers to be written".to_string(),
+            });
+        }
+    }
+
+    count_permit.send(count_msg);
+    commit_token_use(token_use).await;
+    Ok(())
+}
+
+// The vote is in the ledgers, so it's accepted even if the use can't be
+// logged; it still counts until the next restart
+async fn commit_token_use(token_use: Option<TokenUseReservation>) {
+    if let Some(token_use) = token_use {
+        if let Err(err) = token_use.commit().await {
+            error!("Failed to log the token use: {}", err);
+        }
+    }
+}
+
 fn verify_valid_choice(vote: &Vote, choices: &[Choice]) -> Result<()> {
     if !choices
         .iter()
@@ -91,12 +545,313 @@ fn verify_valid_choice(vote: &Vote, choices: &[Choice]) -> Result<()> {
     {
         let message = format!(
             "Choice must be one of {:?}. Received: {:?}",
-            choices, vote.choice
+            choices.iter().map(|c| &c.key).collect::<Vec<_>>(),
+            vote.choice
         );
         return Err(AppError::BadRequest {
-            title: "Invalid choice".to_string(),
+            code: ErrorCode::InvalidChoice,
             message,
         });
     };
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwks::KeyStore;
+    use crate::metrics::Metrics;
+    use crate::models::{
+        Config, CountWorkerShard, CountWorkers, CountsSnapshot, JwtConfig, ReloadStatus,
+    };
+    use crate::rate_limits::RateLimits;
+    use crate::supervisor::WorkerStatus;
+    use crate::test_support::{peppers, temp_filepath};
+    use crate::token_uses::TokenUses;
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{App, HttpMessage, ResponseError};
+    use std::sync::atomic::{AtomicBool, AtomicU64};
+    use std::sync::Arc;
+
+    // Workers are stand-ins: the Counts Worker answers pings and drops its
+    // votes, and the Ledger Worker acknowledges every write or, when
+    // `ledger_writes` is false, drops the messages as if the write failed
+    fn test_state(token_uses: TokenUses, ledger_writes: bool) -> AppState {
+        let (ledger_channel_sender, mut ledger_rx) =
+            tokio::sync::mpsc::channel::<LedgerWorkerMsg>(8);
+        tokio::spawn(async move {
+            while let Some(msg) = ledger_rx.recv().await {
+                if let (true, Some(resp)) = (ledger_writes, msg.resp) {
+                    let _ = resp.send(true);
+                }
+            }
+        });
+        let ledger_worker = WorkerStatus::new("Ledger Worker");
+        ledger_worker.set_up();
+
+        let (count_sender, mut count_rx) = tokio::sync::mpsc::channel::<CountWorkerMsg>(8);
+        tokio::spawn(async move {
+            while let Some(msg) = count_rx.recv().await {
+                if let CountWorkerMsg::Ping { resp } = msg {
+                    let _ = resp.send(());
+                }
+            }
+        });
+        let counts_snapshot = CountsSnapshot::default();
+        counts_snapshot.store(Some(Arc::new(VoteCounts {
+            counts: vec![],
+            invalid: 0,
+        })));
+        let count_status = WorkerStatus::new("Counts Worker 1/1");
+        count_status.set_up();
+        let count_workers = CountWorkers {
+            shards: vec![CountWorkerShard {
+                sender: count_sender,
+                counts_snapshot,
+                status: count_status,
+            }],
+        };
+
+        AppState {
+            peppers: peppers(),
+            config: Config {
+                choices: vec![Choice {
+                    key: "A".to_string(),
+                    label: "A".to_string(),
+                    color: "#000000".to_string(),
+                }],
+            },
+            key_store: KeyStore::new(vec![]),
+            jwt_config: JwtConfig {
+                algorithms: vec![],
+                issuers: vec![],
+                audiences: vec![],
+                leeway_secs: 0,
+                required_claims: vec![],
+            },
+            public_routes: vec![],
+            voting_open: Arc::new(AtomicBool::new(true)),
+            cl_filepath: String::new(),
+            vl_filepath: String::new(),
+            credential_issuer: None,
+            token_uses: Some(token_uses),
+            rate_limits: RateLimits {
+                voter: None,
+                ip: None,
+                trust_forwarded: false,
+            },
+            count_workers,
+            ledger_channel_sender,
+            ledger_worker,
+            queue_send_timeout: Duration::from_secs(1),
+            ledger_write_timeout: Duration::from_secs(1),
+            queue_rejections: Arc::new(AtomicU64::new(0)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            config_reloads: vec![ReloadStatus::default()],
+            metrics: Metrics::new(),
+        }
+    }
+
+    fn voter_request() -> HttpRequest {
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Claims {
+            sub: "voter".to_string(),
+            salt: "AAAAAAAAAAA".to_string(),
+            exp: usize::MAX / 2,
+            jti: Some("jti-1".to_string()),
+            roles: None,
+        });
+        req
+    }
+
+    fn token_uses(name: &str) -> TokenUses {
+        TokenUses::open(&temp_filepath("handlers", name, "log"), 1, 0).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_failed_send_ballot_leaves_the_token_usable() {
+        let vote = Vote {
+            choice: "A".to_string(),
+        };
+        let req = voter_request();
+        let token_uses = token_uses("failed_send");
+
+        let failing = test_state(token_uses.clone(), false);
+        assert!(cast_vote(&failing, &vote, &req).await.is_err());
+
+        // The same token can still vote, once
+        let working = test_state(token_uses, true);
+        assert!(cast_vote(&working, &vote, &req).await.is_ok());
+        assert!(cast_vote(&working, &vote, &req).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_stopped_counts_worker_is_counts_unavailable() {
+        let mut state = test_state(token_uses("counts_stopped"), true);
+        let (count_sender, _) = tokio::sync::mpsc::channel::<CountWorkerMsg>(8);
+        state.count_workers.shards[0].sender = count_sender;
+
+        let err = state.count_workers.request_counts().await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::CountsUnavailable);
+
+        let vote = Vote {
+            choice: "A".to_string(),
+        };
+        let err = cast_vote(&state, &vote, &voter_request())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::CountsUnavailable);
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn test_credentials_disabled() {
+        let state = test_state(token_uses("credentials_disabled"), true);
+        let err = credential_issuer(&state).err().unwrap();
+        assert_eq!(err.code(), ErrorCode::CredentialsDisabled);
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    // The Ledger Worker's messages are handed to the test instead of being
+    // answered, and votes sent to the Counts Worker are kept
+    fn slow_ledger_state(
+        token_uses: TokenUses,
+    ) -> (
+        AppState,
+        tokio::sync::mpsc::Receiver<LedgerWorkerMsg>,
+        tokio::sync::mpsc::Receiver<CountWorkerMsg>,
+    ) {
+        let mut state = test_state(token_uses, true);
+        state.ledger_write_timeout = Duration::from_millis(50);
+        let (ledger_sender, ledger_rx) = tokio::sync::mpsc::channel::<LedgerWorkerMsg>(8);
+        let (count_sender, count_rx) = tokio::sync::mpsc::channel::<CountWorkerMsg>(8);
+        state.ledger_channel_sender = ledger_sender;
+        state.count_workers.shards[0].sender = count_sender;
+        (state, ledger_rx, count_rx)
+    }
+
+    #[actix_web::test]
+    async fn test_slow_ledger_write_times_out_but_is_counted() {
+        let filepath = temp_filepath("handlers", "slow_write", "log");
+        let token_uses = TokenUses::open(&filepath, 1, 0).unwrap();
+        let (state, mut ledger_rx, mut count_rx) = slow_ledger_state(token_uses.clone());
+
+        let vote = Vote {
+            choice: "A".to_string(),
+        };
+        let err = cast_vote(&state, &vote, &voter_request())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::LedgerUnavailable);
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(count_rx.try_recv().is_err());
+        // The use is still held while the write is pending
+        assert!(token_uses.reserve_use("jti-1", 100).is_err());
+
+        // The write lands after the caller gave up, so the vote is in the CL
+        // and has to be counted
+        let msg = ledger_rx.recv().await.unwrap();
+        msg.resp.unwrap().send(true).unwrap();
+        let counted = tokio::time::timeout(Duration::from_secs(1), count_rx.recv()).await;
+        assert!(matches!(counted, Ok(Some(CountWorkerMsg::Vote { .. }))));
+
+        // ...and the token's only use is spent on it
+        let logged = tokio::time::timeout(Duration::from_secs(1), async {
+            while fs::read_to_string(&filepath).unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(logged.is_ok());
+        let err = cast_vote(&state, &vote, &voter_request())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::TokenUsed);
+    }
+
+    #[actix_web::test]
+    async fn test_timed_out_vote_that_is_never_written_gives_the_use_back() {
+        let token_uses = token_uses("slow_write_failed");
+        let (state, mut ledger_rx, _count_rx) = slow_ledger_state(token_uses.clone());
+
+        let vote = Vote {
+            choice: "A".to_string(),
+        };
+        let err = cast_vote(&state, &vote, &voter_request())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::LedgerUnavailable);
+
+        // The write fails, so the token can still vote
+        drop(ledger_rx.recv().await.unwrap());
+        let released = tokio::time::timeout(Duration::from_secs(1), async {
+            while token_uses.reserve_use("jti-1", 100).is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(released.is_ok());
+    }
+
+    async fn readiness(state: AppState) -> (StatusCode, serde_json::Value) {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(web::scope("/health").service(ready)),
+        )
+        .await;
+        let res = call_service(&app, TestRequest::get().uri("/health/ready").to_request()).await;
+        let status = res.status();
+        let body = to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_ready_when_every_worker_is_healthy() {
+        let (status, body) = readiness(test_state(token_uses("ready"), true)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["config_valid"], true);
+        assert_eq!(body["workers"].as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_not_ready_while_a_worker_is_down() {
+        // Restarting, so not yet up again
+        let mut state = test_state(token_uses("worker_down"), true);
+        state.count_workers.shards[0].status = WorkerStatus::new("Counts Worker 1/1");
+        let (status, body) = readiness(state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["workers"][1]["up"], false);
+
+        // Unable to sync the ledgers
+        let state = test_state(token_uses("ledger_down"), false);
+        let (status, body) = readiness(state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ledger_writable"], false);
+    }
+
+    #[actix_web::test]
+    async fn test_failed_reload_is_reported_but_still_ready() {
+        // The previous keys are still served after a bad JWKS or certificate
+        let state = test_state(token_uses("failed_reload"), true);
+        state.config_reloads[0].set_valid(false);
+        let (status, body) = readiness(state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["config_valid"], false);
+        assert_eq!(body["ready"], true);
+    }
+
+    #[actix_web::test]
+    async fn test_not_ready_once_shutdown_has_started() {
+        let state = test_state(token_uses("shutting_down"), true);
+        state.shutting_down.store(true, Ordering::Release);
+        let (status, body) = readiness(state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["shutting_down"], true);
+        assert_eq!(body["ready"], false);
+    }
+}
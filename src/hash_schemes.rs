@@ -0,0 +1,196 @@
+// How the user_id, the voter's salt and the backend pepper are turned into
+// the 12 byte user_id hash written to the CL (16 chars of base64url).
+
+use crate::errors::{AppError, Result};
+use crate::utils::hash_user_id;
+use argon2::{Argon2, Params, Version};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use blake2::digest::{consts::U12, KeyInit, Mac};
+use blake2::Blake2bMac;
+
+const HASH_BYTES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    // Unkeyed Blake2b over user_id, salt and pepper. Anyone holding the
+    // pepper can cheaply hash a list of known user_ids.
+    Blake2b,
+    // Blake2b keyed with the pepper
+    Blake2bMac,
+    // Argon2id with the pepper as its secret; memory-hard, so brute-forcing
+    // known user_ids is slow even with the pepper
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+impl HashScheme {
+    pub const DEFAULT_ARGON2ID: HashScheme = HashScheme::Argon2id {
+        m_cost: Params::DEFAULT_M_COST,
+        t_cost: Params::DEFAULT_T_COST,
+        p_cost: Params::DEFAULT_P_COST,
+    };
+
+    // `blake2b`, `blake2b-mac`, `argon2id` or `argon2id:m=19456,t=2,p=1`
+    pub fn parse(id: &str) -> Option<HashScheme> {
+        match id.trim() {
+            "blake2b" => Some(HashScheme::Blake2b),
+            "blake2b-mac" => Some(HashScheme::Blake2bMac),
+            "argon2id" => Some(HashScheme::DEFAULT_ARGON2ID),
+            id => {
+                let params = id.strip_prefix("argon2id:")?;
+                let (mut m_cost, mut t_cost, mut p_cost) = (None, None, None);
+                for param in params.split(',') {
+                    let (name, value) = param.split_once('=')?;
+                    let value = value.parse().ok()?;
+                    match name {
+                        "m" => m_cost = Some(value),
+                        "t" => t_cost = Some(value),
+                        "p" => p_cost = Some(value),
+                        _ => return None,
+                    }
+                }
+                Some(HashScheme::Argon2id {
+                    m_cost: m_cost?,
+                    t_cost: t_cost?,
+                    p_cost: p_cost?,
+                })
+            }
+        }
+    }
+
+    pub fn id(&self) -> String {
+        match self {
+            HashScheme::Blake2b => "blake2b".to_string(),
+            HashScheme::Blake2bMac => "blake2b-mac".to_string(),
+            HashScheme::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => format!("argon2id:m={},t={},p={}", m_cost, t_cost, p_cost),
+        }
+    }
+
+    // Slow enough that it shouldn't run on the request threads
+    pub fn is_expensive(&self) -> bool {
+        matches!(self, HashScheme::Argon2id { .. })
+    }
+
+    pub fn hash_user_id(&self, user_id: &str, user_salt: &str, pepper: &[u8]) -> Result<String> {
+        let mut hash = [0u8; HASH_BYTES];
+        match self {
+            HashScheme::Blake2b => return hash_user_id(user_id, user_salt, pepper),
+            HashScheme::Blake2bMac => {
+                let user_salt = URL_SAFE_NO_PAD.decode(user_salt)?;
+                let mut mac = <Blake2bMac<U12> as KeyInit>::new_from_slice(pepper)
+                    .map_err(|_| hash_error("Pepper is too long for blake2b-mac"))?;
+                mac.update(user_id.as_bytes());
+                mac.update(&user_salt);
+                hash.copy_from_slice(&mac.finalize().into_bytes());
+            }
+            HashScheme::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                let user_salt = URL_SAFE_NO_PAD.decode(user_salt)?;
+                let params = Params::new(*m_cost, *t_cost, *p_cost, Some(HASH_BYTES))
+                    .map_err(|err| hash_error(&err.to_string()))?;
+                let argon2 = Argon2::new_with_secret(
+                    pepper,
+                    argon2::Algorithm::Argon2id,
+                    Version::V0x13,
+                    params,
+                )
+                .map_err(|err| hash_error(&err.to_string()))?;
+                argon2
+                    .hash_password_into(user_id.as_bytes(), &user_salt, &mut hash)
+                    .map_err(|err| hash_error(&err.to_string()))?;
+            }
+        }
+        Ok(URL_SAFE_NO_PAD.encode(hash))
+    }
+}
+
+fn hash_error(message: &str) -> AppError {
+    AppError::InternalError {
+        title: "User id hash error".to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEPPER: &[u8] = &[7; 8];
+    const USER_SALT: &str = "AAAAAAAAAAA";
+    // Cheap enough for tests
+    const TEST_ARGON2ID: HashScheme = HashScheme::Argon2id {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_parse_round_trips_with_id() {
+        for scheme in [
+            HashScheme::Blake2b,
+            HashScheme::Blake2bMac,
+            HashScheme::DEFAULT_ARGON2ID,
+            TEST_ARGON2ID,
+        ] {
+            assert_eq!(HashScheme::parse(&scheme.id()), Some(scheme));
+        }
+        assert_eq!(
+            HashScheme::parse("argon2id"),
+            Some(HashScheme::DEFAULT_ARGON2ID)
+        );
+        assert_eq!(HashScheme::parse(" blake2b\n"), Some(HashScheme::Blake2b));
+
+        for id in [
+            "",
+            "sha256",
+            "argon2id:",
+            "argon2id:m=64,t=1",
+            "argon2id:m=64,t=1,p=x",
+            "argon2id:m=64,t=1,p=1,q=2",
+        ] {
+            assert_eq!(HashScheme::parse(id), None, "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn test_blake2b_is_the_legacy_hash() {
+        // CLs written before hash schemes existed must still count
+        let hash = HashScheme::Blake2b
+            .hash_user_id("voter", USER_SALT, PEPPER)
+            .unwrap();
+        assert_eq!(hash, hash_user_id("voter", USER_SALT, PEPPER).unwrap());
+        assert_eq!(hash, "mup2SnlFzN9XQXhQ");
+    }
+
+    #[test]
+    fn test_keyed_hashes_are_deterministic() {
+        for (scheme, expected) in [
+            (HashScheme::Blake2bMac, "QTpizLflogy_65Rn"),
+            (TEST_ARGON2ID, "Js3-5QGz230Wr9AD"),
+        ] {
+            let hash = scheme.hash_user_id("voter", USER_SALT, PEPPER).unwrap();
+            assert_eq!(hash, expected, "{}", scheme.id());
+            assert_eq!(hash.len(), 16);
+            assert_ne!(
+                scheme.hash_user_id("voter", USER_SALT, &[8; 8]).unwrap(),
+                hash,
+                "{} depends on the pepper",
+                scheme.id()
+            );
+            assert_ne!(
+                scheme.hash_user_id("other", USER_SALT, PEPPER).unwrap(),
+                hash
+            );
+        }
+    }
+}
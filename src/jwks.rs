@@ -0,0 +1,205 @@
+use crate::errors::{AppError, Result};
+use arc_swap::ArcSwap;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub struct VerificationKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub decoding_key: DecodingKey,
+    // Unix timestamps (seconds) bounding when the key may verify tokens, so
+    // an outgoing key can overlap with its replacement during rotation
+    pub not_before: Option<i64>,
+    pub not_after: Option<i64>,
+}
+
+impl VerificationKey {
+    pub fn is_valid_at(&self, now: i64) -> bool {
+        self.not_before.is_none_or(|nbf| now >= nbf) && self.not_after.is_none_or(|exp| now < exp)
+    }
+}
+
+// The keys used to verify JWTs. Readers never block; a reload swaps in a
+// whole new key list.
+#[derive(Clone)]
+pub struct KeyStore {
+    keys: Arc<ArcSwap<Vec<Arc<VerificationKey>>>>,
+}
+
+impl KeyStore {
+    pub fn new(keys: Vec<VerificationKey>) -> Self {
+        let keys = keys.into_iter().map(Arc::new).collect();
+        Self {
+            keys: Arc::new(ArcSwap::from_pointee(keys)),
+        }
+    }
+
+    pub fn replace(&self, keys: Vec<VerificationKey>) {
+        self.keys
+            .store(Arc::new(keys.into_iter().map(Arc::new).collect()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.load().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Finds the key for a token's `kid` header. Tokens without a `kid` are
+    // only accepted while exactly one key is valid.
+    pub fn find(&self, kid: Option<&str>, now: i64) -> Option<Arc<VerificationKey>> {
+        let keys = self.keys.load();
+        let mut valid_keys = keys.iter().filter(|key| key.is_valid_at(now));
+
+        match kid {
+            Some(kid) => valid_keys
+                .find(|key| key.kid.as_deref() == Some(kid))
+                .cloned(),
+            None => match (valid_keys.next(), valid_keys.next()) {
+                (Some(key), None) => Some(key.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JwksFile {
+    keys: Vec<JwksEntry>,
+}
+
+#[derive(Deserialize)]
+struct JwksEntry {
+    #[serde(flatten)]
+    jwk: Jwk,
+    nbf: Option<i64>,
+    exp: Option<i64>,
+}
+
+pub fn parse_jwks(contents: &str) -> Result<Vec<VerificationKey>> {
+    let jwks: JwksFile = serde_json::from_str(contents)?;
+
+    jwks.keys
+        .into_iter()
+        .map(|entry| {
+            let algorithm = match entry.jwk.common.algorithm {
+                Some(algorithm) => algorithm,
+                None => default_algorithm(&entry.jwk)?,
+            };
+
+            Ok(VerificationKey {
+                kid: entry.jwk.common.key_id.clone(),
+                algorithm,
+                decoding_key: DecodingKey::from_jwk(&entry.jwk)?,
+                not_before: entry.nbf,
+                not_after: entry.exp,
+            })
+        })
+        .collect()
+}
+
+fn default_algorithm(jwk: &Jwk) -> Result<Algorithm> {
+    match &jwk.algorithm {
+        AlgorithmParameters::OctetKeyPair(_) => Ok(Algorithm::EdDSA),
+        AlgorithmParameters::EllipticCurve(params) if params.curve == EllipticCurve::P256 => {
+            Ok(Algorithm::ES256)
+        }
+        AlgorithmParameters::RSA(_) => Ok(Algorithm::RS256),
+        _ => Err(AppError::InternalError {
+            title: "Unsupported JWK".to_string(),
+            message: format!(
+                "Key {:?} needs an \"alg\" parameter",
+                jwk.common.key_id.as_deref().unwrap_or("without kid")
+            ),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: &str = "Xr1qQiFFbGSltytKci6_e28ROZeEBiSl8NaZjReIfvw";
+    const NOW: i64 = 1_800_000_000;
+
+    fn key(kid: Option<&str>, not_before: Option<i64>, not_after: Option<i64>) -> VerificationKey {
+        VerificationKey {
+            kid: kid.map(str::to_string),
+            algorithm: Algorithm::EdDSA,
+            decoding_key: DecodingKey::from_ed_components(X).unwrap(),
+            not_before,
+            not_after,
+        }
+    }
+
+    fn found_kid(key_store: &KeyStore, kid: Option<&str>) -> Option<Option<String>> {
+        key_store.find(kid, NOW).map(|key| key.kid.clone())
+    }
+
+    #[test]
+    fn test_find_matches_kid() {
+        let key_store = KeyStore::new(vec![key(Some("a"), None, None), key(Some("b"), None, None)]);
+        assert_eq!(
+            found_kid(&key_store, Some("b")),
+            Some(Some("b".to_string()))
+        );
+        assert_eq!(found_kid(&key_store, Some("c")), None);
+    }
+
+    #[test]
+    fn test_find_skips_keys_outside_their_validity() {
+        let key_store = KeyStore::new(vec![
+            key(Some("expired"), None, Some(NOW)),
+            key(Some("future"), Some(NOW + 1), None),
+            key(Some("current"), Some(NOW), Some(NOW + 1)),
+        ]);
+        assert_eq!(found_kid(&key_store, Some("expired")), None);
+        assert_eq!(found_kid(&key_store, Some("future")), None);
+        assert_eq!(
+            found_kid(&key_store, Some("current")),
+            Some(Some("current".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_find_without_kid_needs_exactly_one_valid_key() {
+        // The expired key doesn't count
+        let key_store = KeyStore::new(vec![
+            key(Some("old"), None, Some(NOW)),
+            key(Some("new"), None, None),
+        ]);
+        assert_eq!(found_kid(&key_store, None), Some(Some("new".to_string())));
+
+        let key_store = KeyStore::new(vec![key(Some("a"), None, None), key(None, None, None)]);
+        assert_eq!(found_kid(&key_store, None), None);
+
+        assert_eq!(found_kid(&KeyStore::new(Vec::new()), None), None);
+    }
+
+    #[test]
+    fn test_parse_jwks() {
+        let keys = parse_jwks(&std::fs::read_to_string("examples/jwks.json").unwrap()).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].kid.as_deref(), Some("2026-09"));
+        // Ed25519 keys default to EdDSA
+        assert_eq!(keys[0].algorithm, Algorithm::EdDSA);
+        assert_eq!(keys[0].not_before, None);
+        assert_eq!(keys[0].not_after, Some(1792347972));
+        assert_eq!(keys[1].not_before, Some(1792344362));
+        assert_eq!(keys[1].not_after, None);
+    }
+
+    #[test]
+    fn test_parse_jwks_refuses_malformed_input() {
+        assert!(parse_jwks("").is_err());
+        assert!(parse_jwks("{}").is_err());
+        assert!(parse_jwks(r#"{"keys": [{"kty": "OKP"}]}"#).is_err());
+        // A symmetric key has no default algorithm
+        assert!(parse_jwks(r#"{"keys": [{"kty": "oct", "k": "c2VjcmV0"}]}"#).is_err());
+        assert!(parse_jwks(r#"{"keys": []}"#).unwrap().is_empty());
+    }
+}
@@ -1,22 +1,235 @@
-use crate::errors::Result;
-use log::info;
-use std::{fs::File, io::Read, path::Path, time::Instant};
+use crate::counting::utils::RECORD_SIZE;
+use crate::errors::{AppError, Result};
+use crate::hash_schemes::HashScheme;
+use crate::peppers::{read_segments, segments_filepath, upgrade_cl, Peppers};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+use tracing::{debug, info, instrument, warn};
 
+// The CL may start with a `#voterium-cl hash=<scheme>` line naming the
+// user_id hash scheme of its records. CLs without one use blake2b. A CL
+// written by voterium_rekey also has a `pepper=<version>` field: every
+// record it was written with is under that pepper version.
+const CL_HEADER_PREFIX: &str = "#voterium-cl ";
+
+pub fn cl_header_line(scheme: HashScheme) -> String {
+    format!("{}hash={}\n", CL_HEADER_PREFIX, scheme.id())
+}
+
+pub fn rekeyed_cl_header_line(scheme: HashScheme, pepper_version: u32) -> String {
+    format!(
+        "{}hash={} pepper={}\n",
+        CL_HEADER_PREFIX,
+        scheme.id(),
+        pepper_version
+    )
+}
+
+// Length of the header line at the start of `data`, or 0 without one
+pub fn cl_header_len(data: &[u8]) -> usize {
+    if !data.starts_with(CL_HEADER_PREFIX.as_bytes()) {
+        return 0;
+    }
+    memchr::memchr(b'\n', data).map_or(data.len(), |i| i + 1)
+}
+
+// The fields of the CL's header line, or None without one
+fn read_cl_header_fields(filepath: impl AsRef<Path>) -> Result<Option<String>> {
+    let mut line = String::new();
+    match File::open(filepath) {
+        Ok(file) => BufReader::new(file).read_line(&mut line)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(line
+        .strip_prefix(CL_HEADER_PREFIX)
+        .map(|fields| fields.trim_end().to_string()))
+}
+
+pub fn read_cl_header(filepath: impl AsRef<Path>) -> Result<Option<HashScheme>> {
+    let Some(fields) = read_cl_header_fields(filepath)? else {
+        return Ok(None);
+    };
+
+    let scheme = fields
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("hash="))
+        .and_then(HashScheme::parse)
+        .ok_or_else(|| AppError::InternalError {
+            title: "Invalid CL header".to_string(),
+            message: format!("Unknown hash scheme in {:?}", fields),
+        })?;
+    Ok(Some(scheme))
+}
+
+// The pepper version a rekeyed CL was written under, or None for any other
+// CL
+pub fn read_cl_pepper_version(filepath: impl AsRef<Path>) -> Result<Option<u32>> {
+    let Some(fields) = read_cl_header_fields(filepath)? else {
+        return Ok(None);
+    };
+    let Some(version) = fields
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("pepper="))
+    else {
+        return Ok(None);
+    };
+
+    let version = version.parse().map_err(|_| AppError::InternalError {
+        title: "Invalid CL header".to_string(),
+        message: format!("Invalid pepper version in {:?}", fields),
+    })?;
+    Ok(Some(version))
+}
+
+// Writes the header to a new or empty CL, and checks an existing CL uses
+// `scheme`. Hashes can't be converted between schemes, so a CL keeps the
+// scheme it was started with.
+pub fn init_cl_header(filepath: &str, scheme: HashScheme) -> Result<()> {
+    let cl_len = fs::metadata(filepath).map_or(0, |m| m.len());
+    if cl_len == 0 {
+        let mut file = File::create(filepath)?;
+        file.write_all(cl_header_line(scheme).as_bytes())?;
+        file.sync_all()?;
+        return Ok(());
+    }
+    check_cl_scheme(filepath, scheme)
+}
+
+// A CL without a header predates them, when every CL used Blake2b
+fn check_cl_scheme(filepath: &str, scheme: HashScheme) -> Result<()> {
+    let cl_scheme = read_cl_header(filepath)?.unwrap_or(HashScheme::Blake2b);
+    if cl_scheme != scheme {
+        return Err(AppError::InternalError {
+            title: "Hash scheme mismatch".to_string(),
+            message: format!(
+                "CL {} uses {} but user_hash_scheme is {}",
+                filepath,
+                cl_scheme.id(),
+                scheme.id()
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Size of the CL's records, without the header
+pub fn cl_records_len(filepath: impl AsRef<Path>) -> Result<u64> {
+    let file = File::open(filepath)?;
+    let file_len = file.metadata()?.len();
+    let mut header = Vec::with_capacity(64);
+    file.take(256).read_to_end(&mut header)?;
+    Ok(file_len - cl_header_len(&header) as u64)
+}
+
+// Cuts off a partial record left at the end of the CL or VL by a failed
+// write, so later appends stay aligned. Must run before appending.
+pub fn truncate_partial_records(cl_filepath: &str, vl_filepath: &str) -> Result<()> {
+    if let Ok(records_len) = cl_records_len(cl_filepath) {
+        let partial = records_len % RECORD_SIZE as u64;
+        if partial > 0 {
+            let file = fs::OpenOptions::new().write(true).open(cl_filepath)?;
+            file.set_len(file.metadata()?.len() - partial)?;
+            file.sync_all()?;
+            warn!(
+                "Truncated a partial record of {} bytes from the CL",
+                partial
+            );
+        }
+    }
+
+    // VL lines vary in length but are short, so the last newline is near
+    // the end
+    let Ok(mut file) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(vl_filepath)
+    else {
+        return Ok(());
+    };
+    let vl_len = file.metadata()?.len();
+    let tail_start = vl_len.saturating_sub(256);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::with_capacity(256);
+    (&mut file)
+        .take(vl_len - tail_start)
+        .read_to_end(&mut tail)?;
+    if tail.is_empty() || tail.ends_with(b"\n") {
+        return Ok(());
+    }
+    let line_end = match memchr::memrchr(b'\n', &tail) {
+        Some(i) => tail_start + i as u64 + 1,
+        None if tail_start == 0 => 0,
+        // No line end in the tail; leave a VL we don't understand alone
+        None => return Ok(()),
+    };
+    file.set_len(line_end)?;
+    file.sync_all()?;
+    warn!(
+        "Truncated a partial line of {} bytes from the VL",
+        vl_len - line_end
+    );
+    Ok(())
+}
+
+#[instrument(level = "debug", skip_all, fields(filepath = %filepath.as_ref().display()))]
 pub fn load_cl(filepath: impl AsRef<Path>) -> Result<Vec<u8>> {
-    let start_read = Instant::now();
     let mut file = File::open(filepath)?;
     let file_size = file.metadata()?.len() as usize;
 
     let mut data = Vec::with_capacity(file_size);
     file.read_to_end(&mut data)?;
+    // Counting only sees the records
+    data.drain(..cl_header_len(&data));
+    // and only whole ones: a record the Ledger Worker is still appending
+    // hasn't been acknowledged, and would misalign every record before it
+    // when they're read from the end
+    data.truncate(data.len() - data.len() % RECORD_SIZE);
 
-    let duration_read = start_read.elapsed();
-    let file_size_mb = file_size as f64 / (1024.0 * 1024.0);
+    debug!(bytes = file_size, "Read the CL");
 
-    info!(
-        "load_cl - read {:.2} MB in {:?}",
-        file_size_mb, duration_read
-    );
+    Ok(data)
+}
 
+// Loads the CL with every user_id hash under the current pepper version
+pub fn load_current_cl(cl_filepath: &str, peppers: &Peppers) -> Result<Vec<u8>> {
+    let mut data = load_cl(cl_filepath)?;
+    let segments = read_segments(cl_filepath, peppers)?;
+    let upgraded = upgrade_cl(&mut data, &segments, peppers);
+    if upgraded > 0 {
+        info!(
+            "load_current_cl - upgraded {} records to pepper version {}",
+            upgraded,
+            peppers.current_version()
+        );
+    }
     Ok(data)
 }
+
+// Writes the CL at `cl_filepath` to `out_filepath` with every record under
+// the current pepper version, along with a segments file saying so. Both are
+// synced. The header names the version too, so the CL is read correctly
+// even next to a segments file from before the rekey. Returns how many
+// records were written.
+pub fn write_rekeyed_cl(cl_filepath: &str, out_filepath: &str, peppers: &Peppers) -> Result<usize> {
+    let data = load_current_cl(cl_filepath, peppers)?;
+    // The header keeps the CL's hash scheme. The source is only read.
+    check_cl_scheme(cl_filepath, peppers.scheme())?;
+    let mut contents =
+        rekeyed_cl_header_line(peppers.scheme(), peppers.current_version()).into_bytes();
+    contents.extend_from_slice(&data);
+    let segments = format!("{},0\n", peppers.current_version());
+
+    write_synced(out_filepath, &contents)?;
+    write_synced(&segments_filepath(out_filepath), segments.as_bytes())?;
+    Ok(data.len() / RECORD_SIZE)
+}
+
+fn write_synced(filepath: &str, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(filepath)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
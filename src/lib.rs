@@ -1,8 +1,23 @@
 pub mod auth;
+pub mod cors;
 pub mod counting;
+pub mod credentials;
 pub mod errors;
 pub mod handlers;
+pub mod hash_schemes;
+pub mod jwks;
 pub mod ledgers;
+pub mod metrics;
 pub mod models;
+pub mod peppers;
+pub mod rate_limits;
+pub mod server_config;
+pub mod shutdown;
+pub mod supervisor;
+pub mod telemetry;
+#[cfg(test)]
+pub mod test_support;
+pub mod tls;
+pub mod token_uses;
 pub mod utils;
 pub mod workers;
@@ -1,47 +1,265 @@
-use voterium_backend::{auth, handlers, models, utils};
+use voterium_backend::server_config::{ServerArgs, ServerConfig};
+use voterium_backend::{
+    auth, cors, handlers, ledgers, metrics, models, peppers, shutdown, telemetry, tls, utils,
+};
 
-use actix_cors::Cors;
-use actix_web::middleware::from_fn;
-use actix_web::{middleware::Logger, web, App, HttpServer};
+use actix_web::http::header;
+use actix_web::middleware::{from_fn, Condition};
+use actix_web::{web, App, HttpServer};
+use clap::Parser;
 use dotenv::dotenv;
-use env_logger::Env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info};
+use tracing_actix_web::TracingLogger;
+
+// Anything wrong at startup is reported without a panic's backtrace noise,
+// and with the exit status of an invalid configuration
+fn exit_on_error<T, E: fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    })
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+    let args = ServerArgs::parse();
+    let server_config = exit_on_error(ServerConfig::load(&args));
+    // Flushes the exported spans when main returns
+    let _telemetry = telemetry::init_telemetry(exit_on_error(utils::load_telemetry_config()));
+    info!(
+        "Listening on {}://{} with {} workers",
+        server_config.scheme(),
+        server_config.bind,
+        server_config.workers
+    );
 
-    let cl_filepath = utils::load_cl_filepath();
-    let vl_filepath = utils::load_vl_filepath();
-    let config_filepath = utils::load_config_filepath();
+    let cl_filepath = server_config.paths.cl.clone();
+    let vl_filepath = server_config.paths.vl.clone();
+    let config = exit_on_error(utils::load_voting_config(
+        &server_config.paths.voting_config,
+    ));
+    let counting_strategy = exit_on_error(utils::load_counting_strategy(
+        &server_config.counting_strategy,
+        &config.choices,
+    ));
+    let jwt_config = exit_on_error(utils::load_jwt_config(&server_config.auth));
+    let peppers = exit_on_error(utils::load_peppers(&server_config.user_hash_scheme));
+    // Before the Ledger Worker starts appending under the current pepper
+    exit_on_error(
+        ledgers::init_cl_header(&cl_filepath, peppers.scheme())
+            .map_err(|err| format!("Failed to check the CL header: {}", err)),
+    );
+    exit_on_error(
+        ledgers::truncate_partial_records(&cl_filepath, &vl_filepath)
+            .map_err(|err| format!("Failed to repair the CL and VL: {}", err)),
+    );
+    exit_on_error(
+        peppers::start_current_segment(&cl_filepath, &peppers)
+            .map_err(|err| format!("Failed to record the CL pepper version: {}", err)),
+    );
+    let credential_issuer = exit_on_error(utils::load_credential_issuer(&server_config.paths));
+    let metrics = metrics::Metrics::new();
+    let (ledger_channel_sender, ledger_worker) = utils::spawn_ledger_worker(
+        &cl_filepath,
+        &vl_filepath,
+        server_config.queues.ledger_capacity,
+    )
+    .await;
 
-    let config = utils::load_voting_config(&config_filepath);
-    
+    let jwks_reload = models::ReloadStatus::default();
+    let tls_reload = models::ReloadStatus::default();
     let state = models::AppState {
-        backend_salt: utils::load_backend_salt(),
-        decoding_key: utils::load_public_key(),
-        ledger_channel_sender: utils::spawn_ledger_worker(&cl_filepath, &vl_filepath).await,
-        count_channel_sender: utils::spawn_count_worker(config.choices.clone(), &cl_filepath).await,
+        key_store: exit_on_error(utils::load_key_store(
+            &jwt_config,
+            &server_config.auth,
+            &server_config.paths,
+            &jwks_reload,
+        )),
+        token_uses: exit_on_error(utils::load_token_uses(
+            &server_config.auth,
+            &server_config.paths,
+        )),
+        rate_limits: exit_on_error(utils::load_rate_limits(&server_config.rate_limits)),
+        jwt_config,
+        public_routes: exit_on_error(utils::load_public_routes(
+            &server_config.auth.public_routes,
+            credential_issuer.is_some(),
+        )),
+        credential_issuer,
+        voting_open: Arc::new(AtomicBool::new(true)),
+        queue_send_timeout: server_config.queue_send_timeout(),
+        ledger_write_timeout: server_config.ledger_write_timeout(),
+        queue_rejections: Arc::new(AtomicU64::new(0)),
+        ledger_channel_sender,
+        ledger_worker,
+        count_workers: exit_on_error(
+            utils::spawn_count_workers(
+                config.choices.clone(),
+                &cl_filepath,
+                &peppers,
+                server_config.count_worker_shards,
+                counting_strategy,
+                server_config.queues.count_capacity,
+                &metrics,
+            )
+            .await
+            .map_err(|err| format!("Failed to count the CL: {}", err)),
+        ),
         config,
+        peppers,
+        cl_filepath,
+        vl_filepath,
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        config_reloads: vec![jwks_reload, tls_reload.clone()],
+        metrics,
     };
 
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    // Kept outside the server, which holds the only worker senders. The
+    // workers stop once it's gone and their queues are drained.
+    let shutdown_timeout = server_config.shutdown_timeout();
+    let mut workers = vec![state.ledger_worker.clone()];
+    workers.extend(state.count_workers.statuses());
+    let counts_snapshots: Vec<_> = state
+        .count_workers
+        .shards
+        .iter()
+        .map(|shard| shard.counts_snapshot.clone())
+        .collect();
+    let shutting_down = state.shutting_down.clone();
+    let cors_config = server_config.cors.clone();
+    let security_headers = server_config.security_headers.clone();
+    let tls_config = server_config.tls.clone();
+    let hsts = tls_config.as_ref().and_then(|tls_config| {
+        let max_age_secs = tls_config.hsts_max_age_secs?;
+        Some(tls::hsts_header(
+            max_age_secs,
+            tls_config.hsts_include_subdomains,
+        ))
+    });
+    let require_client_certificate = tls_config
+        .as_ref()
+        .is_some_and(|tls_config| tls_config.client_ca_path.is_some());
 
+    let server = HttpServer::new(move || {
+        let mut default_headers = security_headers.default_headers();
+        if let Some(hsts) = &hsts {
+            default_headers =
+                default_headers.add((header::STRICT_TRANSPORT_SECURITY, hsts.clone()));
+        }
+
+        // The last wrap is the outermost, so every response, including
+        // rejections by the middleware inside it, is traced and logged. CORS
+        // is outside authentication so browsers can read its 401s.
         App::new()
-            .wrap(Logger::default())
             .wrap(from_fn(auth::jwt_middleware))
-            .wrap(cors)
+            .wrap(cors::RouteGroupCors::new(&cors_config))
+            .wrap(default_headers)
+            .wrap(TracingLogger::default())
             .app_data(web::Data::new(state.clone()))
+            .app_data(web::JsonConfig::default().error_handler(handlers::json_error_handler))
+            // The groups under /voting are siblings of it, registered first,
+            // rather than nested, as in CORS_ROUTE_GROUPS
+            .service(
+                web::scope("/voting/admin")
+                    // Inside CORS, as preflights carry no client certificate
+                    .wrap(Condition::new(
+                        require_client_certificate,
+                        from_fn(tls::require_client_certificate),
+                    ))
+                    .service(handlers::get_election)
+                    .service(handlers::open_election)
+                    .service(handlers::close_election),
+            )
+            .service(web::scope("/voting/observer").service(handlers::get_ledger_stats))
+            .service(
+                web::scope("/voting/credentials")
+                    .service(handlers::get_credential_key)
+                    .service(handlers::issue_credential),
+            )
+            .service(web::scope("/voting/anonymous").service(handlers::submit_anonymous_vote))
             .service(
                 web::scope("/voting")
                     .service(handlers::submit_vote)
                     .service(handlers::get_results)
+                    .service(handlers::get_invalid_results)
                     .service(handlers::get_config),
             )
+            .service(
+                web::scope("/health")
+                    .service(handlers::live)
+                    .service(handlers::ready),
+            )
+            .service(handlers::get_metrics)
+            .default_service(web::to(handlers::not_found))
     })
-    .workers(1)
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .on_connect(tls::on_connect)
+    .workers(server_config.workers)
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs());
+    let server = match &tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(
+            server_config.bind,
+            exit_on_error(utils::load_tls(tls_config, &tls_reload)),
+        )?,
+        None => server.bind(server_config.bind)?,
+    }
+    .run();
+
+    // Plain HTTP only ever answers with a redirect to the HTTPS address
+    let redirect_server = match tls_config
+        .as_ref()
+        .and_then(|tls_config| tls_config.redirect_http_from)
+    {
+        Some(redirect_http_from) => {
+            let https_port = server_config.bind.port();
+            let security_headers = server_config.security_headers.clone();
+            let redirect_server = HttpServer::new(move || {
+                App::new()
+                    .wrap(security_headers.default_headers())
+                    .app_data(web::Data::new(https_port))
+                    .default_service(web::to(tls::redirect_to_https))
+            })
+            .workers(1)
+            .disable_signals()
+            .bind(redirect_http_from)?
+            .run();
+            info!("Redirecting http://{} to HTTPS", redirect_http_from);
+            let redirect_handle = redirect_server.handle();
+            actix_web::rt::spawn(redirect_server);
+            Some(redirect_handle)
+        }
+        None => None,
+    };
+
+    let shutdown_deadline = exit_on_error(
+        shutdown::spawn_signal_handler(server.handle(), shutting_down, shutdown_timeout)
+            .map_err(|err| format!("Failed to listen for SIGTERM and SIGINT: {}", err)),
+    );
+    server.await?;
+    if let Some(redirect_handle) = redirect_server {
+        redirect_handle.stop(true).await;
+    }
+
+    // The workers get whatever the server's graceful stop left of the timeout
+    let deadline = shutdown_deadline
+        .get()
+        .copied()
+        .unwrap_or_else(|| Instant::now() + shutdown_timeout);
+    shutdown::drain_workers(&workers, deadline.saturating_duration_since(Instant::now())).await;
+    match models::sum_counts_snapshots(counts_snapshots.iter()) {
+        Some(counts) => {
+            let filepath = &server_config.paths.counts_snapshot;
+            match shutdown::write_counts_snapshot(filepath, &counts) {
+                Ok(()) => info!("Wrote final counts to {}: {:?}", filepath, counts.counts),
+                Err(err) => error!("Failed to write final counts to {}: {}", filepath, err),
+            }
+        }
+        None => error!("Counts Workers never finished their initial count; no final counts"),
+    }
+    Ok(())
 }
@@ -0,0 +1,186 @@
+// Prometheus metrics served on /metrics. Counters and histograms are updated
+// as votes come in; the queue and ledger gauges are refreshed when scraped.
+
+use crate::errors::Result;
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+// Hashing takes microseconds with Blake2b and tens of milliseconds with
+// Argon2; a vote waits for the Ledger Worker's sync
+const VOTE_DURATION_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    // By stage: "hash", "send" (queued until written) and "total"
+    vote_duration: HistogramVec,
+    votes_accepted: IntCounter,
+    // By error code
+    votes_rejected: IntCounterVec,
+    // Votes that replaced the voter's earlier vote
+    revotes: IntCounter,
+    // By worker name
+    queue_depth: IntGaugeVec,
+    // By Counts Worker shard
+    unique_voters: IntGaugeVec,
+    // By ledger: "cl" or "vl"
+    ledger_bytes: IntGaugeVec,
+    cl_records: IntGauge,
+    // How long each Counts Worker took to rebuild its counts from the CL
+    counts_rebuild: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let vote_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "voterium_vote_duration_seconds",
+                "Time spent handling a vote, by stage",
+            )
+            .buckets(VOTE_DURATION_BUCKETS.to_vec()),
+            &["stage"],
+        )
+        .unwrap();
+        let votes_accepted = IntCounter::new(
+            "voterium_votes_accepted_total",
+            "Votes written to the ledgers",
+        )
+        .unwrap();
+        let votes_rejected = IntCounterVec::new(
+            Opts::new(
+                "voterium_votes_rejected_total",
+                "Votes refused, by error code",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let revotes = IntCounter::new(
+            "voterium_revotes_total",
+            "Counted votes that replaced the voter's earlier vote",
+        )
+        .unwrap();
+        let queue_depth = IntGaugeVec::new(
+            Opts::new(
+                "voterium_queue_depth",
+                "Messages waiting in a worker's queue",
+            ),
+            &["worker"],
+        )
+        .unwrap();
+        let unique_voters = IntGaugeVec::new(
+            Opts::new(
+                "voterium_unique_voters",
+                "Voters counted by a Counts Worker shard",
+            ),
+            &["shard"],
+        )
+        .unwrap();
+        let ledger_bytes = IntGaugeVec::new(
+            Opts::new("voterium_ledger_bytes", "Size of the CL and VL files"),
+            &["ledger"],
+        )
+        .unwrap();
+        let cl_records = IntGauge::new("voterium_cl_records", "Records in the CL").unwrap();
+        let counts_rebuild = GaugeVec::new(
+            Opts::new(
+                "voterium_counts_rebuild_seconds",
+                "Time the last Counts Worker start took to rebuild its counts from the CL",
+            ),
+            &["shard"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(vote_duration.clone())).unwrap();
+        registry.register(Box::new(votes_accepted.clone())).unwrap();
+        registry.register(Box::new(votes_rejected.clone())).unwrap();
+        registry.register(Box::new(revotes.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(unique_voters.clone())).unwrap();
+        registry.register(Box::new(ledger_bytes.clone())).unwrap();
+        registry.register(Box::new(cl_records.clone())).unwrap();
+        registry.register(Box::new(counts_rebuild.clone())).unwrap();
+
+        Self {
+            registry,
+            vote_duration,
+            votes_accepted,
+            votes_rejected,
+            revotes,
+            queue_depth,
+            unique_voters,
+            ledger_bytes,
+            cl_records,
+            counts_rebuild,
+        }
+    }
+
+    pub fn observe_vote_stage(&self, stage: &str, duration: Duration) {
+        self.vote_duration
+            .with_label_values(&[stage])
+            .observe(duration.as_secs_f64());
+    }
+
+    // Counts the vote as accepted or rejected with its error code, and
+    // observes its total duration
+    pub fn record_vote<T>(&self, result: &Result<T>, duration: Duration) {
+        match result {
+            Ok(_) => self.votes_accepted.inc(),
+            Err(err) => self
+                .votes_rejected
+                .with_label_values(&[err.code().as_str()])
+                .inc(),
+        }
+        self.observe_vote_stage("total", duration);
+    }
+
+    pub fn inc_revotes(&self) {
+        self.revotes.inc();
+    }
+
+    pub fn set_queue_depth(&self, worker: &str, depth: usize) {
+        self.queue_depth
+            .with_label_values(&[worker])
+            .set(depth as i64);
+    }
+
+    pub fn set_unique_voters(&self, shard: usize, voters: usize) {
+        self.unique_voters
+            .with_label_values(&[&shard.to_string()])
+            .set(voters as i64);
+    }
+
+    pub fn set_ledger_sizes(&self, cl_bytes: u64, vl_bytes: u64, cl_records: u64) {
+        self.ledger_bytes
+            .with_label_values(&["cl"])
+            .set(cl_bytes as i64);
+        self.ledger_bytes
+            .with_label_values(&["vl"])
+            .set(vl_bytes as i64);
+        self.cl_records.set(cl_records as i64);
+    }
+
+    pub fn set_counts_rebuild(&self, shard: usize, duration: Duration) {
+        self.counts_rebuild
+            .with_label_values(&[&shard.to_string()])
+            .set(duration.as_secs_f64());
+    }
+
+    // The text exposition format
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
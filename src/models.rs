@@ -1,19 +1,52 @@
-use jsonwebtoken::DecodingKey;
+use crate::auth::PublicRoute;
+use crate::counting::utils::{user_id_hash_u128_from_bytes, voter_partition};
+use crate::credentials::{Credential, CredentialIssuer};
+use crate::errors::{AppError, Result};
+use crate::jwks::KeyStore;
+use crate::metrics::Metrics;
+use crate::peppers::Peppers;
+use crate::rate_limits::{RateLimitStats, RateLimits};
+use crate::supervisor::WorkerStatus;
+use crate::token_uses::TokenUses;
+use arc_swap::ArcSwapOption;
+use jsonwebtoken::Algorithm;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
-use crate::counting::utils::user_id_hash_u128_from_bytes;
+use tracing::Span;
 
 #[derive(Deserialize)]
 pub struct Vote {
     pub choice: String,
 }
 
+#[derive(Deserialize)]
+pub struct AnonymousVote {
+    pub credential: Credential,
+    pub choice: String,
+}
+
+#[derive(Deserialize)]
+pub struct CredentialRequest {
+    pub blinded_message: String,
+}
+
 #[derive(Serialize, Debug, PartialEq, Clone)]
 pub struct VoteCount {
     pub choice: String,
     pub count: u32,
 }
 
+#[derive(Serialize, Debug, PartialEq, Clone, Default)]
+pub struct VoteCounts {
+    pub counts: Vec<VoteCount>,
+    // Voters whose latest CL record has a choice that isn't in the config
+    // or is malformed. They're not counted for any choice.
+    pub invalid: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Choice {
     pub key: String,
@@ -34,11 +67,203 @@ pub struct Config {
 
 #[derive(Clone)]
 pub struct AppState {
-    pub backend_salt: Vec<u8>,
+    // Backend peppers mixed into the user_id hashes
+    pub peppers: Peppers,
     pub config: Config,
-    pub decoding_key: DecodingKey,
-    pub count_channel_sender: Sender<CountWorkerMsg>,
+    pub key_store: KeyStore,
+    pub jwt_config: JwtConfig,
+    // Routes that don't need a token
+    pub public_routes: Vec<PublicRoute>,
+    // Admins can close voting; /vote is refused while it's closed
+    pub voting_open: Arc<AtomicBool>,
+    pub cl_filepath: String,
+    pub vl_filepath: String,
+    // Set when votes are cast with anonymous credentials instead of tokens
+    pub credential_issuer: Option<CredentialIssuer>,
+    // Limits how many votes each token can submit, when JTI_MAX_USES is set
+    pub token_uses: Option<TokenUses>,
+    // Per voter and per IP limits on vote submission
+    pub rate_limits: RateLimits,
+    pub count_workers: CountWorkers,
     pub ledger_channel_sender: Sender<LedgerWorkerMsg>,
+    // Votes are refused while the Ledger Worker is restarting
+    pub ledger_worker: WorkerStatus,
+    // Votes are refused with a 503 when the worker queues stay full this long
+    pub queue_send_timeout: Duration,
+    // and when the Ledger Worker hasn't written them this long after
+    pub ledger_write_timeout: Duration,
+    // Votes refused because the worker queues were full
+    pub queue_rejections: Arc<AtomicU64>,
+    // Set on SIGTERM / SIGINT; votes are refused from then on
+    pub shutting_down: Arc<AtomicBool>,
+    // One per file reloaded while running: the JWKS and the TLS certificate
+    pub config_reloads: Vec<ReloadStatus>,
+    pub metrics: Metrics,
+}
+
+// Whether the last reload of a watched file succeeded. A failed reload keeps
+// the previous contents in use, so it's only reported, not fatal.
+#[derive(Clone)]
+pub struct ReloadStatus {
+    valid: Arc<AtomicBool>,
+}
+
+impl Default for ReloadStatus {
+    fn default() -> Self {
+        // The file was valid when it was first loaded at startup
+        Self {
+            valid: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl ReloadStatus {
+    pub fn is_valid(&self) -> bool {
+        self.valid.load(Ordering::Acquire)
+    }
+
+    pub fn set_valid(&self, valid: bool) {
+        self.valid.store(valid, Ordering::Release);
+    }
+}
+
+// Latest vote counts published by a Counts Worker, sorted by choice key.
+// Empty until the worker has finished its initial count.
+pub type CountsSnapshot = Arc<ArcSwapOption<VoteCounts>>;
+
+#[derive(Clone)]
+pub struct CountWorkerShard {
+    pub sender: Sender<CountWorkerMsg>,
+    pub counts_snapshot: CountsSnapshot,
+    pub status: WorkerStatus,
+}
+
+// Handle to the sharded Counts Workers. Voters are routed to a shard by
+// their user_id_hash, so every vote from one voter goes through the same
+// channel and last-vote-wins holds within that shard.
+#[derive(Clone)]
+pub struct CountWorkers {
+    pub shards: Vec<CountWorkerShard>,
+}
+
+impl CountWorkers {
+    // The channel of the shard that counts this voter
+    pub fn shard_sender(&self, user_id_hash: u128) -> &Sender<CountWorkerMsg> {
+        &self.shards[voter_partition(user_id_hash, self.shards.len())].sender
+    }
+
+    // Sums the published snapshots of every shard. Returns None until all
+    // shards have finished their initial count.
+    pub fn counts(&self) -> Option<VoteCounts> {
+        sum_counts_snapshots(self.shards.iter().map(|shard| &shard.counts_snapshot))
+    }
+
+    // Asks every shard for its counts over the channel and sums them
+    pub async fn request_counts(&self) -> Result<VoteCounts> {
+        let mut shard_counts = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            shard
+                .sender
+                .send(CountWorkerMsg::GetCounts { resp: tx })
+                .await
+                .map_err(|_| AppError::counts_worker_stopped())?;
+            let counts = rx.await.map_err(|_| AppError::counts_worker_stopped())?;
+            shard_counts.push(counts);
+        }
+
+        Ok(sum_shard_counts(shard_counts.iter()))
+    }
+
+    // Whether every shard's worker is running with its counts rebuilt
+    pub fn all_up(&self) -> bool {
+        self.shards.iter().all(|shard| shard.status.is_up())
+    }
+
+    pub fn restarts(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.status.restarts())
+            .sum()
+    }
+
+    pub fn statuses(&self) -> Vec<WorkerStatus> {
+        self.shards
+            .iter()
+            .map(|shard| shard.status.clone())
+            .collect()
+    }
+
+    // Pings every shard's worker, waiting at most `timeout` for each
+    pub async fn ping(&self, timeout: Duration) -> Vec<bool> {
+        let mut responses = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let ping = async {
+                shard
+                    .sender
+                    .send_timeout(CountWorkerMsg::Ping { resp: tx }, timeout)
+                    .await
+                    .ok()?;
+                rx.await.ok()
+            };
+            responses.push(matches!(
+                tokio::time::timeout(timeout, ping).await,
+                Ok(Some(()))
+            ));
+        }
+        responses
+    }
+
+    // Number of messages waiting in each shard's channel
+    pub fn queue_depths(&self) -> Vec<usize> {
+        self.shards
+            .iter()
+            .map(|shard| shard.sender.max_capacity() - shard.sender.capacity())
+            .collect()
+    }
+}
+
+// Sums the published snapshots, or None if any shard hasn't published yet
+pub fn sum_counts_snapshots<'a>(
+    counts_snapshots: impl Iterator<Item = &'a CountsSnapshot>,
+) -> Option<VoteCounts> {
+    let snapshots = counts_snapshots
+        .map(|counts_snapshot| counts_snapshot.load_full())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(sum_shard_counts(snapshots.iter().map(|counts| &**counts)))
+}
+
+pub fn sum_shard_counts<'a>(mut shard_counts: impl Iterator<Item = &'a VoteCounts>) -> VoteCounts {
+    // Every shard reports the same choices in the same order
+    let mut totals = shard_counts.next().cloned().unwrap_or_default();
+    for shard in shard_counts {
+        for (total, vote_count) in totals.counts.iter_mut().zip(&shard.counts) {
+            total.count += vote_count.count;
+        }
+        totals.invalid += shard.invalid;
+    }
+    totals
+}
+
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    // Tokens must be signed with one of these; the first is also the
+    // algorithm of the key at paths.jwt_public_key
+    pub algorithms: Vec<Algorithm>,
+    // When not empty, `iss` / `aud` must be present and match one of these
+    pub issuers: Vec<String>,
+    pub audiences: Vec<String>,
+    pub leeway_secs: u64,
+    pub required_claims: Vec<RequiredClaim>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiredClaim {
+    pub name: String,
+    // If set, the claim (or one of its elements, for arrays) must equal it
+    pub value: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +271,94 @@ pub struct Claims {
     pub sub: String,
     pub salt: String,
     pub exp: usize,
+    // Token id, needed when token uses are limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    // Role names; unknown ones are ignored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+}
+
+impl Claims {
+    // Tokens without a `roles` claim are voters
+    pub fn roles(&self) -> Vec<Role> {
+        match &self.roles {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| Role::from_name(name))
+                .collect(),
+            None => vec![Role::Voter],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Can submit votes
+    Voter,
+    // Can read live ledger statistics
+    Observer,
+    // Can manage the election, and everything an observer can
+    Admin,
+}
+
+impl Role {
+    pub fn from_name(name: &str) -> Option<Role> {
+        match name {
+            "voter" => Some(Role::Voter),
+            "observer" => Some(Role::Observer),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WorkerHealth {
+    pub name: String,
+    // Running with its state recovered
+    pub up: bool,
+    // Answered a ping in time
+    pub responding: bool,
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub shutting_down: bool,
+    // False once a JWKS or TLS certificate reload has failed, until one
+    // succeeds. The previous keys are still served, so it doesn't affect
+    // `ready`.
+    pub config_valid: bool,
+    // The Ledger Worker synced a ping
+    pub ledger_writable: bool,
+    // Every Counts Worker has rebuilt its counts from the CL
+    pub initial_counts_done: bool,
+    pub workers: Vec<WorkerHealth>,
+}
+
+#[derive(Serialize)]
+pub struct LedgerStats {
+    pub voting_open: bool,
+    pub cl_records: u64,
+    pub cl_bytes: u64,
+    pub vl_bytes: u64,
+    pub ledger_queue_depth: usize,
+    pub ledger_queue_capacity: usize,
+    pub count_queue_depths: Vec<usize>,
+    pub count_queue_capacity: usize,
+    // False while a worker is restarting
+    pub workers_up: bool,
+    pub worker_restarts: u64,
+    // Votes refused because the worker queues were full
+    pub queue_rejections: u64,
+    // Votes refused by the rate limits since startup
+    pub rate_limited: RateLimitStats,
+    // None until the Counts Workers finish their initial count
+    pub counts: Option<VoteCounts>,
 }
 
 pub struct Ballot {
@@ -88,11 +401,17 @@ impl From<&Ballot> for CountWorkerBallot {
 }
 
 pub enum CountWorkerMsg {
+    // `span` is the request's, so the worker's events carry its request_id
     Vote {
         ballot: CountWorkerBallot,
+        span: Span,
     },
     GetCounts {
-        resp: tokio::sync::oneshot::Sender<Vec<VoteCount>>,
+        resp: tokio::sync::oneshot::Sender<VoteCounts>,
+    },
+    // Answered straight away, to check the worker is handling messages
+    Ping {
+        resp: tokio::sync::oneshot::Sender<()>,
     },
 }
 
@@ -100,6 +419,21 @@ pub struct LedgerWorkerMsg {
     pub vl_line: Vec<u8>,
     pub cl_line: Vec<u8>,
     pub resp: Option<tokio::sync::oneshot::Sender<bool>>,
+    // The span of the request that sent the message
+    pub span: Span,
+}
+
+impl LedgerWorkerMsg {
+    // Writes nothing; answered once the batch it's in has been synced, so
+    // it also checks the ledgers are writable
+    pub fn ping(resp: tokio::sync::oneshot::Sender<bool>) -> Self {
+        Self {
+            vl_line: Vec::new(),
+            cl_line: Vec::new(),
+            resp: Some(resp),
+            span: Span::current(),
+        }
+    }
 }
 
 impl From<&Ballot> for LedgerWorkerMsg {
@@ -108,6 +442,7 @@ impl From<&Ballot> for LedgerWorkerMsg {
             vl_line: ballot.to_vl_line().into_bytes(),
             cl_line: ballot.to_cl_line().into_bytes(),
             resp: None,
+            span: Span::current(),
         }
     }
 }
@@ -0,0 +1,429 @@
+// Versioned backend peppers.
+//
+// The first pepper (the original BACKEND_SALT) is mixed into the user_id
+// hash by the configured HashScheme. Each later version re-hashes the previous version's
+// hash with its own pepper, so a hash can be moved to a newer version
+// without knowing the user_id. If an old pepper leaks, hashes under a
+// newer version still can't be recomputed from identity data.
+//
+// The CL doesn't record a version per record. Instead `<cl>.peppers` lists
+// `version,offset` lines giving the byte offset where each version's
+// records start, and older records are upgraded in memory when the CL is
+// loaded. `voterium_rekey` rewrites a CL so every record uses the current
+// version, and names that version in the CL's header.
+
+use crate::counting::utils::{is_well_formed_record, HASH_SIZE, RECORD_SIZE};
+use crate::errors::{AppError, Result};
+use crate::hash_schemes::HashScheme;
+use crate::ledgers::{cl_records_len, read_cl_pepper_version};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use blake2::{digest::consts::U12, Blake2b, Digest};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Pepper {
+    pub version: u32,
+    pub secret: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Peppers {
+    // Sorted by version; never empty
+    peppers: Arc<Vec<Pepper>>,
+    // How the base hash is computed from the user_id and the first pepper
+    scheme: HashScheme,
+}
+
+impl Peppers {
+    pub fn new(mut peppers: Vec<Pepper>, scheme: HashScheme) -> Result<Self> {
+        peppers.sort_by_key(|pepper| pepper.version);
+        if peppers.is_empty() {
+            return Err(pepper_error("At least one pepper is needed"));
+        }
+        if peppers
+            .windows(2)
+            .any(|pair| pair[0].version == pair[1].version)
+        {
+            return Err(pepper_error("Pepper versions must be unique"));
+        }
+        Ok(Self {
+            peppers: Arc::new(peppers),
+            scheme,
+        })
+    }
+
+    // Only the original BACKEND_SALT, as version 1
+    pub fn single(secret: Vec<u8>, scheme: HashScheme) -> Self {
+        Self {
+            peppers: Arc::new(vec![Pepper { version: 1, secret }]),
+            scheme,
+        }
+    }
+
+    pub fn scheme(&self) -> HashScheme {
+        self.scheme
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.peppers.last().unwrap().version
+    }
+
+    pub fn first_version(&self) -> u32 {
+        self.peppers[0].version
+    }
+
+    pub fn has_version(&self, version: u32) -> bool {
+        self.peppers.iter().any(|pepper| pepper.version == version)
+    }
+
+    // The first pepper, which every hash starts from
+    pub fn base_secret(&self) -> &[u8] {
+        &self.peppers[0].secret
+    }
+
+    // The user_id hash under the first pepper only. It never changes when
+    // peppers are added, but is only as secret as the first pepper.
+    pub fn base_hash_user_id(&self, user_id: &str, user_salt: &str) -> Result<String> {
+        self.scheme
+            .hash_user_id(user_id, user_salt, self.base_secret())
+    }
+
+    // The user_id hash under the current version, as written to the CL
+    pub fn hash_user_id(&self, user_id: &str, user_salt: &str) -> Result<String> {
+        let base_hash = self.base_hash_user_id(user_id, user_salt)?;
+        self.upgrade(&base_hash, self.first_version())
+    }
+
+    // Moves a hash made under `from_version` to the current version
+    pub fn upgrade(&self, hash: &str, from_version: u32) -> Result<String> {
+        let mut raw = URL_SAFE_NO_PAD.decode(hash)?;
+        for pepper in self.peppers.iter().filter(|p| p.version > from_version) {
+            let mut hasher = Blake2b::<U12>::new();
+            hasher.update(b"voterium-pepper-v1");
+            hasher.update(pepper.version.to_be_bytes());
+            hasher.update(&raw);
+            hasher.update(&pepper.secret);
+            raw = hasher.finalize().to_vec();
+        }
+        Ok(URL_SAFE_NO_PAD.encode(raw))
+    }
+}
+
+pub fn segments_filepath(cl_filepath: &str) -> String {
+    format!("{}.peppers", cl_filepath)
+}
+
+// The `(version, offset)` segments of a CL. Without a segments file the
+// whole CL is under the first pepper.
+//
+// A rekeyed CL's header version wins over older versions in the segments
+// file. An in-place rekey replaces the CL before its segments file, so a
+// crash in between leaves the old segments next to the rekeyed records.
+//
+// Every version must still be configured: upgrading records without one of
+// their peppers would silently give them different hashes.
+pub fn read_segments(cl_filepath: &str, peppers: &Peppers) -> Result<Vec<(u32, u64)>> {
+    let mut segments = match fs::read_to_string(segments_filepath(cl_filepath)) {
+        Ok(contents) => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split_once(',')
+                    .and_then(|(version, offset)| {
+                        Some((version.trim().parse().ok()?, offset.trim().parse().ok()?))
+                    })
+                    .ok_or_else(|| pepper_error(&format!("Malformed pepper segment {:?}", line)))
+            })
+            .collect::<Result<Vec<(u32, u64)>>>()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            vec![(peppers.first_version(), 0)]
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if let Some(rekeyed_version) = read_cl_pepper_version(cl_filepath)? {
+        for (version, _) in segments.iter_mut() {
+            *version = (*version).max(rekeyed_version);
+        }
+    }
+    if let Some((version, _)) = segments
+        .iter()
+        .find(|(version, _)| !peppers.has_version(*version))
+    {
+        return Err(pepper_error(&format!(
+            "CL has records under pepper version {}, which isn't configured",
+            version
+        )));
+    }
+    Ok(segments)
+}
+
+// Records that CL records from here on use the current pepper. Must run
+// before the Ledger Worker appends anything.
+pub fn start_current_segment(cl_filepath: &str, peppers: &Peppers) -> Result<()> {
+    // Also refuses versions that aren't configured, including newer ones
+    let segments = read_segments(cl_filepath, peppers)?;
+    let current = peppers.current_version();
+    if segments.last().map(|(version, _)| *version) == Some(current) {
+        return Ok(());
+    }
+
+    // Offsets count from the first record, after the CL header
+    let cl_len = cl_records_len(cl_filepath).unwrap_or(0);
+    let segments_filepath = segments_filepath(cl_filepath);
+    let created = !Path::new(&segments_filepath).exists();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&segments_filepath)?;
+    // Existing records predate the segments file, so they're under the
+    // first pepper
+    if created && cl_len > 0 {
+        writeln!(file, "{},0", peppers.first_version())?;
+    }
+    writeln!(file, "{},{}", current, cl_len)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// Rewrites the user_id hashes of older segments in `data` to the current
+// pepper version. Returns how many records were upgraded.
+pub fn upgrade_cl(data: &mut [u8], segments: &[(u32, u64)], peppers: &Peppers) -> usize {
+    let current = peppers.current_version();
+    let mut upgraded = 0;
+
+    for (i, (version, start)) in segments.iter().enumerate() {
+        if *version == current {
+            continue;
+        }
+        let end = segments
+            .get(i + 1)
+            .map_or(data.len(), |(_, offset)| *offset as usize)
+            .min(data.len());
+        let start = *start as usize;
+
+        for record in data[start.min(end)..end].chunks_exact_mut(RECORD_SIZE) {
+            if !is_well_formed_record(record) {
+                continue;
+            }
+            let Ok(hash) = std::str::from_utf8(&record[..HASH_SIZE]) else {
+                continue;
+            };
+            let Ok(new_hash) = peppers.upgrade(hash, *version) else {
+                continue;
+            };
+            record[..HASH_SIZE].copy_from_slice(new_hash.as_bytes());
+            upgraded += 1;
+        }
+    }
+    upgraded
+}
+
+fn pepper_error(message: &str) -> AppError {
+    AppError::InternalError {
+        title: "Pepper error".to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting::utils::{
+        counts_from_latest_votes, make_choices_lookup, make_latest_votes_hashmap,
+    };
+    use crate::ledgers::{init_cl_header, load_cl, load_current_cl, write_rekeyed_cl};
+    use crate::test_support::temp_filepath;
+    use crate::utils::load_voting_config;
+
+    const USER_SALT: &str = "AAAAAAAAAAA";
+
+    fn peppers(versions: &[u32]) -> Peppers {
+        Peppers::new(
+            versions
+                .iter()
+                .map(|&version| Pepper {
+                    version,
+                    secret: vec![version as u8; 8],
+                })
+                .collect(),
+            HashScheme::Blake2b,
+        )
+        .unwrap()
+    }
+
+    fn cl_filepath(name: &str) -> String {
+        let filepath = temp_filepath("peppers", name, "csv");
+        let _ = fs::remove_file(segments_filepath(&filepath));
+        filepath
+    }
+
+    fn latest_vote_counts(data: &[u8]) -> (Vec<u32>, u32) {
+        let choices = load_voting_config("examples/voting_config_ABC.json")
+            .unwrap()
+            .choices;
+        let latest_votes = make_latest_votes_hashmap(data, make_choices_lookup(&choices), 0, 1);
+        counts_from_latest_votes(&latest_votes, &choices)
+    }
+
+    #[test]
+    fn test_hash_user_id_is_the_upgraded_base_hash() -> Result<()> {
+        let v1 = peppers(&[1]);
+        let v3 = peppers(&[1, 2, 3]);
+
+        let base_hash = v3.base_hash_user_id("voter", USER_SALT)?;
+        // The base hash only depends on the first pepper
+        assert_eq!(base_hash, v1.hash_user_id("voter", USER_SALT)?);
+        assert_eq!(
+            v3.hash_user_id("voter", USER_SALT)?,
+            v3.upgrade(&base_hash, 1)?
+        );
+
+        // Upgrading one version at a time gives the same hash
+        let v2_hash = peppers(&[1, 2]).upgrade(&base_hash, 1)?;
+        assert_ne!(v2_hash, base_hash);
+        assert_eq!(
+            v3.upgrade(&v2_hash, 2)?,
+            v3.hash_user_id("voter", USER_SALT)?
+        );
+
+        // Hashes under the current version are left alone
+        let current = v3.hash_user_id("voter", USER_SALT)?;
+        assert_eq!(v3.upgrade(&current, 3)?, current);
+        assert_ne!(
+            current,
+            v3.hash_user_id("other voter", USER_SALT)?,
+            "distinct voters keep distinct hashes"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rekeyed_cl_counts_the_same() -> Result<()> {
+        let cl_filepath = cl_filepath("rekey");
+        let v1 = peppers(&[1]);
+        let v2 = peppers(&[1, 2]);
+
+        // Records written under the first pepper, then under the second
+        init_cl_header(&cl_filepath, v1.scheme())?;
+        start_current_segment(&cl_filepath, &v1)?;
+        let v1_records = fs::read("examples/cl_10.csv")?;
+        OpenOptions::new()
+            .append(true)
+            .open(&cl_filepath)?
+            .write_all(&v1_records)?;
+        start_current_segment(&cl_filepath, &v2)?;
+        // A voter from the first segment changes their vote
+        let revoter = std::str::from_utf8(&v1_records[..HASH_SIZE]).unwrap();
+        let revote = format!("{},1730291337999,B\n", v2.upgrade(revoter, 1)?);
+        OpenOptions::new()
+            .append(true)
+            .open(&cl_filepath)?
+            .write_all(revote.as_bytes())?;
+
+        let current = load_current_cl(&cl_filepath, &v2)?;
+        let before = latest_vote_counts(&current);
+        // The same votes in a CL that only ever had the first pepper, so the
+        // revote replaces the first segment's vote only if it was upgraded
+        let mut v1_only = v1_records.clone();
+        v1_only.extend_from_slice(format!("{},1730291337999,B\n", revoter).as_bytes());
+        assert_eq!(before, latest_vote_counts(&v1_only));
+
+        let out_filepath = cl_filepath.replace(".csv", ".rekeyed.csv");
+        assert_eq!(
+            write_rekeyed_cl(&cl_filepath, &out_filepath, &v2)?,
+            current.len() / RECORD_SIZE
+        );
+        // Every record is under the current pepper, so nothing is upgraded
+        assert_eq!(read_segments(&out_filepath, &v2)?, vec![(2, 0)]);
+        assert_eq!(load_cl(&out_filepath)?, current);
+        assert_eq!(
+            latest_vote_counts(&load_current_cl(&out_filepath, &v2)?),
+            before
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_between_the_in_place_rekey_renames() -> Result<()> {
+        let cl_filepath = cl_filepath("rekey_crash");
+        let v1 = peppers(&[1]);
+        let v2 = peppers(&[1, 2]);
+
+        init_cl_header(&cl_filepath, v1.scheme())?;
+        start_current_segment(&cl_filepath, &v1)?;
+        OpenOptions::new()
+            .append(true)
+            .open(&cl_filepath)?
+            .write_all(&fs::read("examples/cl_10.csv")?)?;
+        start_current_segment(&cl_filepath, &v2)?;
+        let current = load_current_cl(&cl_filepath, &v2)?;
+
+        // As voterium_rekey --in-place, stopping after the CL's rename
+        let tmp_filepath = format!("{}.rekeyed.tmp", cl_filepath);
+        write_rekeyed_cl(&cl_filepath, &tmp_filepath, &v2)?;
+        fs::rename(&tmp_filepath, &cl_filepath)?;
+        assert_eq!(
+            fs::read_to_string(segments_filepath(&cl_filepath))?,
+            "1,0\n2,330\n"
+        );
+
+        // The header says every record is already under version 2, so none
+        // is upgraded a second time
+        assert_eq!(read_segments(&cl_filepath, &v2)?, vec![(2, 0), (2, 330)]);
+        assert_eq!(load_current_cl(&cl_filepath, &v2)?, current);
+        // and the backend can carry on appending under it
+        start_current_segment(&cl_filepath, &v2)?;
+        assert_eq!(load_current_cl(&cl_filepath, &v2)?, current);
+        Ok(())
+    }
+
+    #[test]
+    fn test_segments_need_every_pepper_version() -> Result<()> {
+        let cl_filepath = cl_filepath("missing_version");
+        init_cl_header(&cl_filepath, HashScheme::Blake2b)?;
+        start_current_segment(&cl_filepath, &peppers(&[1]))?;
+        OpenOptions::new()
+            .append(true)
+            .open(&cl_filepath)?
+            .write_all(&fs::read("examples/cl_10.csv")?)?;
+        start_current_segment(&cl_filepath, &peppers(&[1, 2]))?;
+        start_current_segment(&cl_filepath, &peppers(&[1, 2, 3]))?;
+
+        // Version 2 was dropped from the configuration
+        let without_v2 = peppers(&[1, 3]);
+        assert!(read_segments(&cl_filepath, &without_v2).is_err());
+        assert!(load_current_cl(&cl_filepath, &without_v2).is_err());
+        assert!(start_current_segment(&cl_filepath, &without_v2).is_err());
+        // as was version 3, now newer than the current one
+        assert!(start_current_segment(&cl_filepath, &peppers(&[1, 2])).is_err());
+
+        load_current_cl(&cl_filepath, &peppers(&[1, 2, 3]))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rekeying_leaves_the_source_cl_alone() -> Result<()> {
+        let source_filepath = cl_filepath("rekey_source");
+        let out_filepath = source_filepath.replace(".csv", ".rekeyed.csv");
+        let v1 = peppers(&[1]);
+
+        // An empty CL gets no header
+        fs::write(&source_filepath, b"")?;
+        assert_eq!(write_rekeyed_cl(&source_filepath, &out_filepath, &v1)?, 0);
+        assert!(fs::read(&source_filepath)?.is_empty());
+
+        // Nor is one under another scheme, which isn't rekeyed
+        let mac_filepath = cl_filepath("rekey_source_mac");
+        init_cl_header(&mac_filepath, HashScheme::Blake2bMac)?;
+        let contents = fs::read(&mac_filepath)?;
+        let mac_out_filepath = cl_filepath("rekey_source_mac_out");
+        assert!(write_rekeyed_cl(&mac_filepath, &mac_out_filepath, &v1).is_err());
+        assert_eq!(fs::read(&mac_filepath)?, contents);
+        assert!(fs::metadata(&mac_out_filepath).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+// Token bucket rate limits for vote submission, keyed by user_id_hash (or
+// nullifier, for anonymous votes) and by client IP.
+
+use crate::errors::{AppError, Result};
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Each key gets `burst` requests, refilled evenly over `period`
+#[derive(Clone)]
+pub struct RateLimiter {
+    burst: f64,
+    refill_per_sec: f64,
+    buckets: Arc<Mutex<FxHashMap<String, Bucket>>>,
+    // Requests refused since startup
+    throttled: Arc<AtomicU64>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, period: Duration) -> Self {
+        Self {
+            burst: burst as f64,
+            refill_per_sec: burst as f64 / period.as_secs_f64(),
+            buckets: Arc::new(Mutex::new(FxHashMap::default())),
+            throttled: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // `BURST/SECONDS`, e.g. `5/60` for 5 requests a minute
+    pub fn parse(limit: &str) -> Option<Self> {
+        let (burst, secs) = limit.trim().split_once('/')?;
+        let burst: u32 = burst.trim().parse().ok()?;
+        let secs: u64 = secs.trim().parse().ok()?;
+        if burst == 0 || secs == 0 {
+            return None;
+        }
+        Some(Self::new(burst, Duration::from_secs(secs)))
+    }
+
+    // Takes a token from the key's bucket, or fails with how long until
+    // one is available
+    pub fn check(&self, key: &str, now: Instant) -> Result<()> {
+        let mut buckets = self.buckets.lock().expect("Rate limit lock poisoned");
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        let wait_secs = (1.0 - bucket.tokens) / self.refill_per_sec;
+        Err(AppError::TooManyRequests {
+            message: "Too many votes; try again later".to_string(),
+            retry_after_secs: wait_secs.ceil() as u64,
+        })
+    }
+
+    // Forgets keys whose buckets have refilled, since a new bucket starts
+    // full anyway
+    pub fn prune(&self, now: Instant) -> usize {
+        let mut buckets = self.buckets.lock().expect("Rate limit lock poisoned");
+        let before = buckets.len();
+        buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        before - buckets.len()
+    }
+
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_sec).min(self.burst)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RateLimits {
+    pub voter: Option<RateLimiter>,
+    pub ip: Option<RateLimiter>,
+    // Take the client IP from the last Forwarded / X-Forwarded-For entry;
+    // only safe behind a single proxy that appends to them
+    pub trust_forwarded: bool,
+}
+
+#[derive(Serialize)]
+pub struct RateLimitStats {
+    pub voter_throttled: u64,
+    pub ip_throttled: u64,
+}
+
+impl RateLimits {
+    pub fn check_ip(&self, req: &HttpRequest) -> Result<()> {
+        let Some(limiter) = &self.ip else {
+            return Ok(());
+        };
+        let forwarded = self.trust_forwarded.then(|| forwarded_for(req)).flatten();
+        let ip = match forwarded {
+            Some(ip) => ip,
+            None => req
+                .connection_info()
+                .peer_addr()
+                .unwrap_or("unknown")
+                .to_string(),
+        };
+        limiter.check(&ip, Instant::now())
+    }
+
+    pub fn check_voter(&self, user_id_hash: &str) -> Result<()> {
+        match &self.voter {
+            Some(limiter) => limiter.check(user_id_hash, Instant::now()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn prune(&self, now: Instant) -> usize {
+        self.voter
+            .iter()
+            .chain(&self.ip)
+            .map(|l| l.prune(now))
+            .sum()
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        let throttled =
+            |limiter: &Option<RateLimiter>| limiter.as_ref().map_or(0, |l| l.throttled());
+        RateLimitStats {
+            voter_throttled: throttled(&self.voter),
+            ip_throttled: throttled(&self.ip),
+        }
+    }
+}
+
+// The address the trusted proxy saw the request come from. Proxies append
+// to Forwarded and X-Forwarded-For, so the right-most entry is the one our
+// proxy added; anything left of it was sent by the client.
+fn forwarded_for(req: &HttpRequest) -> Option<String> {
+    let last_entry = |name| {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .last()
+    };
+
+    if let Some(element) = last_entry(header::FORWARDED) {
+        let for_ = element.split(';').find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            name.eq_ignore_ascii_case("for")
+                .then(|| value.trim_matches('"').to_string())
+        });
+        if for_.is_some() {
+            return for_;
+        }
+    }
+    last_entry(header::X_FORWARDED_FOR).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use actix_web::ResponseError;
+
+    fn retry_after(result: Result<()>) -> u64 {
+        match result {
+            Err(AppError::TooManyRequests {
+                retry_after_secs, ..
+            }) => retry_after_secs,
+            other => panic!("Expected TooManyRequests, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_check_refills_over_time() {
+        // 2 requests every 10 seconds: one token every 5
+        let limiter = RateLimiter::parse("2/10").unwrap();
+        let start = Instant::now();
+        assert!(limiter.check("voter", start).is_ok());
+        assert!(limiter.check("voter", start).is_ok());
+        assert_eq!(retry_after(limiter.check("voter", start)), 5);
+        // Other keys have their own buckets
+        assert!(limiter.check("other", start).is_ok());
+
+        let later = start + Duration::from_secs(2);
+        assert_eq!(retry_after(limiter.check("voter", later)), 3);
+        let refilled = start + Duration::from_secs(5);
+        assert!(limiter.check("voter", refilled).is_ok());
+        assert!(limiter.check("voter", refilled).is_err());
+        assert_eq!(limiter.throttled(), 3);
+    }
+
+    #[test]
+    fn test_rejection_carries_retry_after() {
+        let limiter = RateLimiter::parse("1/60").unwrap();
+        let now = Instant::now();
+        limiter.check("voter", now).unwrap();
+        let response = limiter.check("voter", now).unwrap_err().error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "60");
+    }
+
+    #[test]
+    fn test_parse_refuses_empty_limits() {
+        for limit in ["0/60", "5/0", "5", "x/60", ""] {
+            assert!(RateLimiter::parse(limit).is_none(), "{:?}", limit);
+        }
+    }
+
+    #[test]
+    fn test_prune_forgets_refilled_buckets() {
+        let limiter = RateLimiter::parse("2/10").unwrap();
+        let start = Instant::now();
+        limiter.check("a", start).unwrap();
+        limiter.check("b", start + Duration::from_secs(4)).unwrap();
+
+        assert_eq!(limiter.prune(start + Duration::from_secs(4)), 0);
+        // a has refilled, b hasn't
+        assert_eq!(limiter.prune(start + Duration::from_secs(6)), 1);
+        assert_eq!(limiter.prune(start + Duration::from_secs(9)), 1);
+    }
+
+    #[test]
+    fn test_forwarded_for_takes_the_proxys_entry() {
+        let req = TestRequest::default()
+            .insert_header((header::X_FORWARDED_FOR, "6.6.6.6, 10.0.0.1"))
+            .to_http_request();
+        assert_eq!(forwarded_for(&req).as_deref(), Some("10.0.0.1"));
+
+        // Across repeated headers too
+        let req = TestRequest::default()
+            .append_header((header::X_FORWARDED_FOR, "6.6.6.6"))
+            .append_header((header::X_FORWARDED_FOR, "10.0.0.1"))
+            .to_http_request();
+        assert_eq!(forwarded_for(&req).as_deref(), Some("10.0.0.1"));
+
+        let req = TestRequest::default()
+            .insert_header((
+                header::FORWARDED,
+                "for=6.6.6.6, for=\"[2001:db8::1]:4711\";proto=https",
+            ))
+            .to_http_request();
+        assert_eq!(forwarded_for(&req).as_deref(), Some("[2001:db8::1]:4711"));
+
+        assert_eq!(
+            forwarded_for(&TestRequest::default().to_http_request()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_spoofed_forwarded_entries_share_the_proxys_bucket() {
+        let limits = RateLimits {
+            voter: None,
+            ip: RateLimiter::parse("1/60"),
+            trust_forwarded: true,
+        };
+        let request = |spoofed: &str| {
+            TestRequest::default()
+                .insert_header((header::X_FORWARDED_FOR, format!("{}, 10.0.0.1", spoofed)))
+                .to_http_request()
+        };
+        assert!(limits.check_ip(&request("1.1.1.1")).is_ok());
+        assert!(limits.check_ip(&request("2.2.2.2")).is_err());
+    }
+}
@@ -0,0 +1,1147 @@
+// Server settings: where to listen, TLS, HTTP workers, CORS, security
+// headers, the worker queues, token verification, rate limits and the
+// files the server reads and writes. Secrets such as BACKEND_SALT stay in
+// the environment. Built from the defaults,
+// then an optional TOML file (--config or SERVER_CONFIG), then environment
+// variables, then command line flags, and validated before anything is
+// started.
+
+use crate::auth::{parse_algorithm, PublicRoute};
+use crate::counting::strategies;
+use crate::hash_schemes::HashScheme;
+use crate::rate_limits::RateLimiter;
+use actix_cors::Cors;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::middleware::DefaultHeaders;
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Parser, Debug, Default)]
+#[command(about = "Voterium backend")]
+pub struct ServerArgs {
+    /// TOML server configuration; defaults to SERVER_CONFIG
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<String>,
+    /// Address to listen on, e.g. 0.0.0.0:8080
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+    /// Number of HTTP worker threads
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// PEM certificate chain to serve HTTPS with
+    #[arg(long, value_name = "FILE")]
+    pub tls_cert: Option<String>,
+    /// PEM private key of the certificate
+    #[arg(long, value_name = "FILE")]
+    pub tls_key: Option<String>,
+    /// Origin allowed to call the API from a browser; repeat for several
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    pub cors_origins: Vec<String>,
+    /// Commitment Ledger
+    #[arg(long, value_name = "FILE")]
+    pub cl: Option<String>,
+    /// Vote Ledger
+    #[arg(long, value_name = "FILE")]
+    pub vl: Option<String>,
+    /// Choices of the election, as JSON
+    #[arg(long, value_name = "FILE")]
+    pub voting_config: Option<String>,
+    /// Slots in the Ledger Worker's queue
+    #[arg(long, value_name = "SLOTS")]
+    pub ledger_queue_capacity: Option<usize>,
+    /// Slots in each Counts Worker's queue
+    #[arg(long, value_name = "SLOTS")]
+    pub count_queue_capacity: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    pub workers: usize,
+    // How long shutdown waits for in-flight requests, and then for the
+    // workers to drain their queues
+    pub shutdown_timeout_secs: u64,
+    pub count_worker_shards: usize,
+    // Makes the latest votes the Counts Workers start from
+    pub counting_strategy: String,
+    // blake2b, blake2b-mac or argon2id[:m=,t=,p=]. Recorded in the CL
+    // header; an existing CL keeps the scheme it started with.
+    pub user_hash_scheme: String,
+    pub tls: Option<TlsConfig>,
+    pub cors: CorsConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub queues: QueueConfig,
+    pub auth: AuthConfig,
+    pub rate_limits: RateLimitConfig,
+    pub paths: PathConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    // PEM certificate chain and private key
+    pub cert_path: String,
+    pub key_path: String,
+    // How often the files are checked for a renewed certificate
+    pub reload_interval_secs: u64,
+    // Plain HTTP address whose requests are redirected to HTTPS
+    pub redirect_http_from: Option<SocketAddr>,
+    // Sends Strict-Transport-Security with every response when set
+    pub hsts_max_age_secs: Option<u64>,
+    pub hsts_include_subdomains: bool,
+    // PEM CA certificates; when set, admin routes need a client certificate
+    // signed by one of them
+    pub client_ca_path: Option<String>,
+}
+
+// The route groups whose CORS settings can be changed separately, with the
+// path each covers. The groups under /voting come before it so the most
+// specific prefix is found first. Unknown routes never get CORS headers.
+pub const CORS_ROUTE_GROUPS: &[(&str, &str)] = &[
+    ("admin", "/voting/admin"),
+    ("observer", "/voting/observer"),
+    ("credentials", "/voting/credentials"),
+    ("anonymous", "/voting/anonymous"),
+    ("voting", "/voting"),
+    ("health", "/health"),
+    ("metrics", "/metrics"),
+];
+
+// Browsers may only call the API from the listed origins. Each route group
+// uses these settings unless it overrides them under `groups.<name>`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // `scheme://host[:port]` origins, or `*` for any; none by default
+    pub allowed_origins: Vec<String>,
+    // Methods and request headers, or `*` for any
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    // How long browsers may cache a preflight's answer
+    pub max_age_secs: usize,
+    pub groups: BTreeMap<String, CorsGroupConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsGroupConfig {
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+}
+
+// Sent with every response unless the handler set the header itself. An
+// empty value leaves the header out.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    pub content_security_policy: String,
+    pub referrer_policy: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    // Slots in each worker's channel
+    pub ledger_capacity: usize,
+    pub count_capacity: usize,
+    // Votes are refused with a 503 when the queues stay full this long
+    pub send_timeout_ms: u64,
+    // or when the Ledger Worker hasn't synced them within this long
+    pub write_timeout_ms: u64,
+}
+
+// Token verification, and the routes that don't need a token
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // EdDSA, ES256 or RS256; the first is the algorithm of
+    // paths.jwt_public_key
+    pub jwt_algorithms: Vec<String>,
+    // When not empty, `iss` / `aud` must match one of these
+    pub jwt_issuers: Vec<String>,
+    pub jwt_audiences: Vec<String>,
+    pub jwt_leeway_secs: u64,
+    // `name` or `name=value`
+    pub jwt_required_claims: Vec<String>,
+    // How often paths.jwks is checked for changes
+    pub jwks_reload_interval_secs: u64,
+    // `[METHOD ]PATTERN`, see PublicRoute. The health checks are always
+    // public, and so are the anonymous voting routes when credentials are
+    // enabled.
+    pub public_routes: Vec<String>,
+    // Votes each token id (jti) may make. When set, tokens without a jti
+    // are refused and the ids are kept in paths.jti_store.
+    pub jti_max_uses: Option<u32>,
+    pub jti_prune_interval_secs: u64,
+}
+
+// Token bucket limits on votes as `BURST/SECONDS`, e.g. `5/60`, per
+// user_id hash and per client IP; each is off unless set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub voter: Option<String>,
+    pub ip: Option<String>,
+    // Take the client IP from Forwarded / X-Forwarded-For; only behind a
+    // proxy that sets them
+    pub trust_forwarded: bool,
+    pub prune_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathConfig {
+    pub cl: String,
+    pub vl: String,
+    pub voting_config: String,
+    // Final counts written on shutdown
+    pub counts_snapshot: String,
+    // Tokens are verified with the PEM public key, or with the keys in a
+    // JWKS file, which is reloaded when it changes. One is required.
+    pub jwt_public_key: Option<String>,
+    pub jwks: Option<String>,
+    // Token ids used so far; required with auth.jti_max_uses
+    pub jti_store: Option<String>,
+    // RSA private key PEM that enables anonymous credentials, and where
+    // the credentials issued are recorded; required with it
+    pub credential_key: Option<String>,
+    pub credential_issued: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            workers: 1,
+            shutdown_timeout_secs: 30,
+            count_worker_shards: 1,
+            counting_strategy: strategies::DEFAULT_STRATEGY.to_string(),
+            user_hash_scheme: HashScheme::Blake2b.id(),
+            tls: None,
+            cors: CorsConfig::default(),
+            security_headers: SecurityHeadersConfig::default(),
+            queues: QueueConfig::default(),
+            auth: AuthConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            paths: PathConfig::default(),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: String::new(),
+            key_path: String::new(),
+            reload_interval_secs: 60,
+            redirect_http_from: None,
+            hsts_max_age_secs: None,
+            hsts_include_subdomains: false,
+            client_ca_path: None,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Authorization".to_string(), "Content-Type".to_string()],
+            max_age_secs: 3600,
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+// The API only serves JSON, so nothing may be loaded, framed or referred
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            content_security_policy: "default-src 'none'; frame-ancestors 'none'".to_string(),
+            referrer_policy: "no-referrer".to_string(),
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            ledger_capacity: 10_000,
+            count_capacity: 10_000,
+            send_timeout_ms: 100,
+            write_timeout_ms: 5_000,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_algorithms: vec!["EdDSA".to_string()],
+            jwt_issuers: Vec::new(),
+            jwt_audiences: Vec::new(),
+            jwt_leeway_secs: 60,
+            jwt_required_claims: Vec::new(),
+            jwks_reload_interval_secs: 10,
+            public_routes: vec![
+                "GET /voting/config".to_string(),
+                "GET /voting/results".to_string(),
+                "GET /voting/results/invalid".to_string(),
+            ],
+            jti_max_uses: None,
+            jti_prune_interval_secs: 60,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            voter: None,
+            ip: None,
+            trust_forwarded: false,
+            prune_interval_secs: 60,
+        }
+    }
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        Self {
+            cl: "cl.csv".to_string(),
+            vl: "vl.csv".to_string(),
+            voting_config: "voting_config.json".to_string(),
+            counts_snapshot: "counts_snapshot.json".to_string(),
+            jwt_public_key: None,
+            jwks: None,
+            jti_store: None,
+            credential_key: None,
+            credential_issued: None,
+        }
+    }
+}
+
+// Every problem found in the configuration, so they can be fixed at once
+#[derive(Debug)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+// The environment loaders stop at their first problem
+impl From<String> for ConfigError {
+    fn from(error: String) -> Self {
+        Self {
+            errors: vec![error],
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    pub fn load(args: &ServerArgs) -> Result<Self, ConfigError> {
+        let mut errors = Vec::new();
+
+        let filepath = args
+            .config
+            .clone()
+            .or_else(|| env::var("SERVER_CONFIG").ok());
+        let mut config = match &filepath {
+            Some(filepath) => match read_toml(filepath) {
+                Ok(config) => config,
+                // Nothing else is worth checking against a file that's wrong
+                Err(error) => {
+                    return Err(ConfigError {
+                        errors: vec![error],
+                    })
+                }
+            },
+            None => ServerConfig::default(),
+        };
+
+        config.apply_env(&mut errors);
+        config.apply_args(args);
+        config.validate(&mut errors);
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { errors })
+        }
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    pub fn queue_send_timeout(&self) -> Duration {
+        Duration::from_millis(self.queues.send_timeout_ms)
+    }
+
+    pub fn ledger_write_timeout(&self) -> Duration {
+        Duration::from_millis(self.queues.write_timeout_ms)
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        env_override("BIND_ADDRESS", &mut self.bind, errors);
+        env_override("HTTP_WORKERS", &mut self.workers, errors);
+        env_override(
+            "SHUTDOWN_TIMEOUT_SECS",
+            &mut self.shutdown_timeout_secs,
+            errors,
+        );
+        env_override("COUNT_WORKER_SHARDS", &mut self.count_worker_shards, errors);
+        env_override("COUNTING_STRATEGY", &mut self.counting_strategy, errors);
+        env_override("USER_HASH_SCHEME", &mut self.user_hash_scheme, errors);
+        if let Ok(cert_path) = env::var("TLS_CERT_PATH") {
+            self.tls.get_or_insert_with(TlsConfig::default).cert_path = cert_path;
+        }
+        if let Ok(key_path) = env::var("TLS_KEY_PATH") {
+            self.tls.get_or_insert_with(TlsConfig::default).key_path = key_path;
+        }
+        // The other TLS settings only apply once a certificate is configured
+        if let Some(tls) = &mut self.tls {
+            env_override(
+                "TLS_RELOAD_INTERVAL_SECS",
+                &mut tls.reload_interval_secs,
+                errors,
+            );
+            env_override_some(
+                "TLS_REDIRECT_HTTP_FROM",
+                &mut tls.redirect_http_from,
+                errors,
+            );
+            env_override_some("HSTS_MAX_AGE_SECS", &mut tls.hsts_max_age_secs, errors);
+            env_override(
+                "HSTS_INCLUDE_SUBDOMAINS",
+                &mut tls.hsts_include_subdomains,
+                errors,
+            );
+            env_override_some("TLS_CLIENT_CA_PATH", &mut tls.client_ca_path, errors);
+        }
+        env_list("CORS_ALLOWED_ORIGINS", &mut self.cors.allowed_origins);
+        env_list("CORS_ALLOWED_METHODS", &mut self.cors.allowed_methods);
+        env_list("CORS_ALLOWED_HEADERS", &mut self.cors.allowed_headers);
+        env_override("CORS_MAX_AGE_SECS", &mut self.cors.max_age_secs, errors);
+        env_override(
+            "CONTENT_SECURITY_POLICY",
+            &mut self.security_headers.content_security_policy,
+            errors,
+        );
+        env_override(
+            "REFERRER_POLICY",
+            &mut self.security_headers.referrer_policy,
+            errors,
+        );
+        env_override(
+            "LEDGER_QUEUE_CAPACITY",
+            &mut self.queues.ledger_capacity,
+            errors,
+        );
+        env_override(
+            "COUNT_QUEUE_CAPACITY",
+            &mut self.queues.count_capacity,
+            errors,
+        );
+        env_override(
+            "QUEUE_SEND_TIMEOUT_MS",
+            &mut self.queues.send_timeout_ms,
+            errors,
+        );
+        env_override(
+            "LEDGER_WRITE_TIMEOUT_MS",
+            &mut self.queues.write_timeout_ms,
+            errors,
+        );
+        env_override("CL_FILEPATH", &mut self.paths.cl, errors);
+        env_override("VL_FILEPATH", &mut self.paths.vl, errors);
+        env_override("CONFIG_FILEPATH", &mut self.paths.voting_config, errors);
+        env_override(
+            "COUNTS_SNAPSHOT_FILEPATH",
+            &mut self.paths.counts_snapshot,
+            errors,
+        );
+        env_override_some(
+            "JWT_PUBLIC_KEY_PATH",
+            &mut self.paths.jwt_public_key,
+            errors,
+        );
+        env_override_some("JWT_JWKS_PATH", &mut self.paths.jwks, errors);
+        env_override_some("JTI_STORE_PATH", &mut self.paths.jti_store, errors);
+        env_override_some(
+            "CREDENTIAL_KEY_PATH",
+            &mut self.paths.credential_key,
+            errors,
+        );
+        env_override_some(
+            "CREDENTIAL_ISSUED_PATH",
+            &mut self.paths.credential_issued,
+            errors,
+        );
+
+        let auth = &mut self.auth;
+        env_list("JWT_ALGORITHMS", &mut auth.jwt_algorithms);
+        env_list("JWT_ISSUER", &mut auth.jwt_issuers);
+        env_list("JWT_AUDIENCE", &mut auth.jwt_audiences);
+        env_override("JWT_LEEWAY_SECS", &mut auth.jwt_leeway_secs, errors);
+        env_list("JWT_REQUIRED_CLAIMS", &mut auth.jwt_required_claims);
+        env_override(
+            "JWKS_RELOAD_INTERVAL_SECS",
+            &mut auth.jwks_reload_interval_secs,
+            errors,
+        );
+        env_list("PUBLIC_ROUTES", &mut auth.public_routes);
+        env_override_some("JTI_MAX_USES", &mut auth.jti_max_uses, errors);
+        env_override(
+            "JTI_PRUNE_INTERVAL_SECS",
+            &mut auth.jti_prune_interval_secs,
+            errors,
+        );
+
+        let rate_limits = &mut self.rate_limits;
+        env_override_some("VOTER_RATE_LIMIT", &mut rate_limits.voter, errors);
+        env_override_some("IP_RATE_LIMIT", &mut rate_limits.ip, errors);
+        env_override(
+            "RATE_LIMIT_TRUST_FORWARDED",
+            &mut rate_limits.trust_forwarded,
+            errors,
+        );
+        env_override(
+            "RATE_LIMIT_PRUNE_INTERVAL_SECS",
+            &mut rate_limits.prune_interval_secs,
+            errors,
+        );
+    }
+
+    fn apply_args(&mut self, args: &ServerArgs) {
+        if let Some(bind) = args.bind {
+            self.bind = bind;
+        }
+        if let Some(workers) = args.workers {
+            self.workers = workers;
+        }
+        if let Some(cert_path) = &args.tls_cert {
+            self.tls.get_or_insert_with(TlsConfig::default).cert_path = cert_path.clone();
+        }
+        if let Some(key_path) = &args.tls_key {
+            self.tls.get_or_insert_with(TlsConfig::default).key_path = key_path.clone();
+        }
+        if !args.cors_origins.is_empty() {
+            self.cors.allowed_origins = args.cors_origins.clone();
+        }
+        if let Some(cl) = &args.cl {
+            self.paths.cl = cl.clone();
+        }
+        if let Some(vl) = &args.vl {
+            self.paths.vl = vl.clone();
+        }
+        if let Some(voting_config) = &args.voting_config {
+            self.paths.voting_config = voting_config.clone();
+        }
+        if let Some(capacity) = args.ledger_queue_capacity {
+            self.queues.ledger_capacity = capacity;
+        }
+        if let Some(capacity) = args.count_queue_capacity {
+            self.queues.count_capacity = capacity;
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.workers == 0 {
+            errors.push("workers must be at least 1".to_string());
+        }
+        if self.count_worker_shards == 0 {
+            errors.push("count_worker_shards must be at least 1".to_string());
+        }
+        if self.queues.ledger_capacity == 0 {
+            errors.push("queues.ledger_capacity must be at least 1".to_string());
+        }
+        if self.queues.count_capacity == 0 {
+            errors.push("queues.count_capacity must be at least 1".to_string());
+        }
+        if self.queues.write_timeout_ms == 0 {
+            errors.push("queues.write_timeout_ms must be at least 1".to_string());
+        }
+        if strategies::find_strategy(&self.counting_strategy).is_none() {
+            errors.push(format!(
+                "counting_strategy: unknown strategy {:?}; must be one of {:?}",
+                self.counting_strategy,
+                strategies::strategy_names()
+            ));
+        }
+        if HashScheme::parse(&self.user_hash_scheme).is_none() {
+            errors.push(format!(
+                "user_hash_scheme: {:?} must be blake2b, blake2b-mac or argon2id[:m=,t=,p=]",
+                self.user_hash_scheme
+            ));
+        }
+
+        if let Some(tls) = &self.tls {
+            check_readable_file("tls.cert_path", &tls.cert_path, errors);
+            check_readable_file("tls.key_path", &tls.key_path, errors);
+            if let Some(ca_path) = &tls.client_ca_path {
+                check_readable_file("tls.client_ca_path", ca_path, errors);
+            }
+            if tls.reload_interval_secs == 0 {
+                errors.push("tls.reload_interval_secs must be at least 1".to_string());
+            }
+            if tls.redirect_http_from == Some(self.bind) {
+                errors.push("tls.redirect_http_from must differ from bind".to_string());
+            }
+            if tls.hsts_include_subdomains && tls.hsts_max_age_secs.is_none() {
+                errors.push("tls.hsts_include_subdomains needs tls.hsts_max_age_secs".to_string());
+            }
+        }
+
+        self.cors.validate(errors);
+        self.security_headers.validate(errors);
+        self.auth.validate(errors);
+        self.rate_limits.validate(errors);
+        self.paths.validate(&self.auth, errors);
+    }
+}
+
+impl AuthConfig {
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.jwt_algorithms.is_empty() {
+            errors.push("auth.jwt_algorithms must list at least one algorithm".to_string());
+        }
+        for algorithm in &self.jwt_algorithms {
+            if parse_algorithm(algorithm).is_none() {
+                errors.push(format!(
+                    "auth.jwt_algorithms: {:?} must be EdDSA, ES256 or RS256",
+                    algorithm
+                ));
+            }
+        }
+        for route in &self.public_routes {
+            if PublicRoute::parse(route).is_none() {
+                errors.push(format!(
+                    "auth.public_routes: {:?} isn't `[METHOD ]PATTERN`",
+                    route
+                ));
+            }
+        }
+        if self.jwks_reload_interval_secs == 0 {
+            errors.push("auth.jwks_reload_interval_secs must be at least 1".to_string());
+        }
+        if self.jti_max_uses == Some(0) {
+            errors.push("auth.jti_max_uses must be at least 1".to_string());
+        }
+        if self.jti_prune_interval_secs == 0 {
+            errors.push("auth.jti_prune_interval_secs must be at least 1".to_string());
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn validate(&self, errors: &mut Vec<String>) {
+        let limits = [
+            ("rate_limits.voter", &self.voter),
+            ("rate_limits.ip", &self.ip),
+        ];
+        for (field, limit) in limits {
+            if let Some(limit) = limit {
+                if RateLimiter::parse(limit).is_none() {
+                    errors.push(format!(
+                        "{}: {:?} must be BURST/SECONDS, e.g. 5/60",
+                        field, limit
+                    ));
+                }
+            }
+        }
+        if self.prune_interval_secs == 0 {
+            errors.push("rate_limits.prune_interval_secs must be at least 1".to_string());
+        }
+    }
+}
+
+impl PathConfig {
+    fn validate(&self, auth: &AuthConfig, errors: &mut Vec<String>) {
+        check_readable_file("paths.voting_config", &self.voting_config, errors);
+        check_parent_dir("paths.cl", &self.cl, errors);
+        check_parent_dir("paths.vl", &self.vl, errors);
+        check_parent_dir("paths.counts_snapshot", &self.counts_snapshot, errors);
+
+        if self.jwt_public_key.is_none() && self.jwks.is_none() {
+            errors.push("paths.jwt_public_key or paths.jwks is required".to_string());
+        }
+        if let Some(jwt_public_key) = &self.jwt_public_key {
+            check_readable_file("paths.jwt_public_key", jwt_public_key, errors);
+        }
+        if let Some(jwks) = &self.jwks {
+            check_readable_file("paths.jwks", jwks, errors);
+        }
+        match &self.jti_store {
+            Some(jti_store) => check_parent_dir("paths.jti_store", jti_store, errors),
+            None if auth.jti_max_uses.is_some() => {
+                errors.push("paths.jti_store is required with auth.jti_max_uses".to_string())
+            }
+            None => {}
+        }
+        if let Some(credential_key) = &self.credential_key {
+            check_readable_file("paths.credential_key", credential_key, errors);
+            match &self.credential_issued {
+                Some(issued) => check_parent_dir("paths.credential_issued", issued, errors),
+                None => errors.push(
+                    "paths.credential_issued is required with paths.credential_key".to_string(),
+                ),
+            }
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval_secs)
+    }
+}
+
+impl CorsConfig {
+    // The CORS middleware of a route group. Requests never carry cookies, so
+    // `*` origins get a wildcard answer rather than the echoed origin.
+    pub fn cors(&self, group: &str) -> Cors {
+        let overrides = self.groups.get(group).cloned().unwrap_or_default();
+        let origins = overrides
+            .allowed_origins
+            .unwrap_or(self.allowed_origins.clone());
+        let methods = overrides
+            .allowed_methods
+            .unwrap_or(self.allowed_methods.clone());
+        let headers = overrides
+            .allowed_headers
+            .unwrap_or(self.allowed_headers.clone());
+
+        let mut cors = Cors::default().max_age(self.max_age_secs);
+        cors = if origins.iter().any(|origin| origin == "*") {
+            cors.allow_any_origin().send_wildcard()
+        } else {
+            origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin))
+        };
+        cors = if methods.iter().any(|method| method == "*") {
+            cors.allow_any_method()
+        } else {
+            cors.allowed_methods(methods.iter().map(String::as_str))
+        };
+        if headers.iter().any(|header| header == "*") {
+            cors.allow_any_header()
+        } else {
+            cors.allowed_headers(headers.iter().map(String::as_str))
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        check_cors_lists(
+            "cors",
+            Some(&self.allowed_origins),
+            Some(&self.allowed_methods),
+            Some(&self.allowed_headers),
+            errors,
+        );
+        for (group, overrides) in &self.groups {
+            if !CORS_ROUTE_GROUPS.iter().any(|(name, _)| name == group) {
+                let names: Vec<_> = CORS_ROUTE_GROUPS.iter().map(|(name, _)| *name).collect();
+                errors.push(format!(
+                    "cors.groups: unknown route group {:?}; must be one of {}",
+                    group,
+                    names.join(", ")
+                ));
+                continue;
+            }
+            check_cors_lists(
+                &format!("cors.groups.{}", group),
+                overrides.allowed_origins.as_ref(),
+                overrides.allowed_methods.as_ref(),
+                overrides.allowed_headers.as_ref(),
+                errors,
+            );
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    pub fn default_headers(&self) -> DefaultHeaders {
+        let headers = [
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (
+                header::CONTENT_SECURITY_POLICY,
+                self.content_security_policy.as_str(),
+            ),
+            (header::REFERRER_POLICY, self.referrer_policy.as_str()),
+        ];
+        headers
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .fold(DefaultHeaders::new(), |default_headers, header| {
+                default_headers.add(header)
+            })
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let headers = [
+            (
+                "security_headers.content_security_policy",
+                &self.content_security_policy,
+            ),
+            ("security_headers.referrer_policy", &self.referrer_policy),
+        ];
+        for (field, value) in headers {
+            if HeaderValue::from_str(value).is_err() {
+                errors.push(format!("{}: {:?} isn't a valid header value", field, value));
+            }
+        }
+    }
+}
+
+fn read_toml(filepath: &str) -> Result<ServerConfig, String> {
+    let contents = fs::read_to_string(filepath)
+        .map_err(|err| format!("Can't read server config {}: {}", filepath, err))?;
+    toml::from_str(&contents).map_err(|err| format!("Invalid server config {}: {}", filepath, err))
+}
+
+fn env_override<T>(name: &str, value: &mut T, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Ok(raw) = env::var(name) {
+        match raw.parse() {
+            Ok(parsed) => *value = parsed,
+            Err(err) => errors.push(format!("{}={:?}: {}", name, raw, err)),
+        }
+    }
+}
+
+// A comma separated list
+fn env_list(name: &str, value: &mut Vec<String>) {
+    if let Ok(raw) = env::var(name) {
+        *value = raw
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect();
+    }
+}
+
+fn env_override_some<T>(name: &str, value: &mut Option<T>, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Ok(raw) = env::var(name) {
+        match raw.parse() {
+            Ok(parsed) => *value = Some(parsed),
+            Err(err) => errors.push(format!("{}={:?}: {}", name, raw, err)),
+        }
+    }
+}
+
+fn check_readable_file(field: &str, filepath: &str, errors: &mut Vec<String>) {
+    if filepath.is_empty() {
+        errors.push(format!("{} is required", field));
+    } else if let Err(err) = fs::File::open(filepath) {
+        errors.push(format!("{}: can't read {:?}: {}", field, filepath, err));
+    }
+}
+
+// The file may not exist yet, but it must be possible to create it
+fn check_parent_dir(field: &str, filepath: &str, errors: &mut Vec<String>) {
+    let parent = match Path::new(filepath).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        errors.push(format!(
+            "{}: directory {:?} doesn't exist",
+            field,
+            parent.display()
+        ));
+    }
+}
+
+fn check_cors_lists(
+    field: &str,
+    origins: Option<&Vec<String>>,
+    methods: Option<&Vec<String>>,
+    headers: Option<&Vec<String>>,
+    errors: &mut Vec<String>,
+) {
+    for origin in origins.into_iter().flatten() {
+        if let Err(error) = check_origin(origin) {
+            errors.push(format!("{}.allowed_origins: {:?} {}", field, origin, error));
+        }
+    }
+    for method in methods.into_iter().flatten() {
+        if method != "*" && Method::from_bytes(method.as_bytes()).is_err() {
+            errors.push(format!(
+                "{}.allowed_methods: {:?} isn't a method",
+                field, method
+            ));
+        }
+    }
+    for name in headers.into_iter().flatten() {
+        if name != "*" && HeaderName::from_bytes(name.as_bytes()).is_err() {
+            errors.push(format!(
+                "{}.allowed_headers: {:?} isn't a header name",
+                field, name
+            ));
+        }
+    }
+}
+
+fn check_origin(origin: &str) -> Result<(), &'static str> {
+    if origin == "*" {
+        return Ok(());
+    }
+    let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or("must start with http:// or https://")?;
+    if host.is_empty() || host.contains('/') {
+        return Err("must be scheme://host[:port] with no path");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{temp_filepath, with_env};
+
+    const VOTING_CONFIG: &str = "examples/voting_config_ABC.json";
+    const JWKS: &str = "examples/jwks.json";
+
+    // With the example JWKS as the token keys, which `vars` can override
+    fn load_with_env(
+        args: &ServerArgs,
+        vars: &[(&str, &str)],
+    ) -> Result<ServerConfig, ConfigError> {
+        let mut vars = vars.to_vec();
+        if !vars.iter().any(|(name, _)| *name == "JWT_JWKS_PATH") {
+            vars.push(("JWT_JWKS_PATH", JWKS));
+        }
+        with_env(&vars, || ServerConfig::load(args))
+    }
+
+    fn args() -> ServerArgs {
+        ServerArgs {
+            voting_config: Some(VOTING_CONFIG.to_string()),
+            ..ServerArgs::default()
+        }
+    }
+
+    fn toml_filepath(name: &str, contents: &str) -> String {
+        let filepath = temp_filepath("server_config", name, "toml");
+        fs::write(&filepath, contents).unwrap();
+        filepath
+    }
+
+    fn refused(result: Result<ServerConfig, ConfigError>) -> Vec<String> {
+        result.expect_err("configuration should be refused").errors
+    }
+
+    #[test]
+    fn test_env_overrides_toml_and_flags_override_both() {
+        let config_filepath = toml_filepath(
+            "precedence",
+            "workers = 2\ncount_worker_shards = 2\n\n[paths]\ncl = \"toml_cl.csv\"\nvl = \"toml_vl.csv\"\n",
+        );
+        let args = ServerArgs {
+            config: Some(config_filepath),
+            workers: Some(4),
+            ..args()
+        };
+
+        let config = load_with_env(
+            &args,
+            &[("HTTP_WORKERS", "3"), ("CL_FILEPATH", "env_cl.csv")],
+        )
+        .unwrap();
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.paths.cl, "env_cl.csv");
+        assert_eq!(config.paths.vl, "toml_vl.csv");
+        assert_eq!(config.count_worker_shards, 2);
+        assert_eq!(config.paths.voting_config, VOTING_CONFIG);
+        // Not set anywhere
+        assert_eq!(config.bind, ServerConfig::default().bind);
+    }
+
+    #[test]
+    fn test_example_server_toml_loads() {
+        // Only whether the key file can be read is checked here
+        let key_filepath = temp_filepath("server_config", "example_key", "pub");
+        fs::write(&key_filepath, "").unwrap();
+        let args = ServerArgs {
+            config: Some("examples/server.toml".to_string()),
+            ..args()
+        };
+        let config = load_with_env(&args, &[("JWT_PUBLIC_KEY_PATH", &key_filepath)]).unwrap();
+        assert_eq!(
+            config.auth.public_routes,
+            AuthConfig::default().public_routes
+        );
+    }
+
+    #[test]
+    fn test_auth_and_rate_limits_come_from_env() {
+        let jti_filepath = temp_filepath("server_config", "jti", "log");
+        let config = load_with_env(
+            &args(),
+            &[
+                ("JWT_ALGORITHMS", "ES256, RS256"),
+                ("JWT_AUDIENCE", "voting"),
+                ("PUBLIC_ROUTES", "GET /voting/config,GET /metrics"),
+                ("JTI_MAX_USES", "2"),
+                ("JTI_STORE_PATH", &jti_filepath),
+                ("VOTER_RATE_LIMIT", "5/60"),
+                ("RATE_LIMIT_TRUST_FORWARDED", "true"),
+                ("COUNTING_STRATEGY", "count_votes_36"),
+                ("USER_HASH_SCHEME", "blake2b-mac"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.auth.jwt_algorithms, ["ES256", "RS256"]);
+        assert_eq!(config.auth.jwt_audiences, ["voting"]);
+        assert_eq!(
+            config.auth.public_routes,
+            ["GET /voting/config", "GET /metrics"]
+        );
+        assert_eq!(config.auth.jti_max_uses, Some(2));
+        assert_eq!(
+            config.paths.jti_store.as_deref(),
+            Some(jti_filepath.as_str())
+        );
+        assert_eq!(config.paths.jwks.as_deref(), Some(JWKS));
+        assert_eq!(config.rate_limits.voter.as_deref(), Some("5/60"));
+        assert_eq!(config.rate_limits.ip, None);
+        assert!(config.rate_limits.trust_forwarded);
+        assert_eq!(config.counting_strategy, "count_votes_36");
+        assert_eq!(config.user_hash_scheme, "blake2b-mac");
+    }
+
+    #[test]
+    fn test_a_token_key_is_required() {
+        let errors = refused(with_env(&[], || ServerConfig::load(&args())));
+        assert_eq!(errors, ["paths.jwt_public_key or paths.jwks is required"]);
+
+        let errors = refused(load_with_env(
+            &args(),
+            &[("JWT_PUBLIC_KEY_PATH", "missing/key.pub")],
+        ));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("paths.jwt_public_key: can't read"));
+    }
+
+    #[test]
+    fn test_token_ids_and_credentials_need_their_files() {
+        let errors = refused(load_with_env(
+            &args(),
+            &[("JTI_MAX_USES", "1"), ("CREDENTIAL_KEY_PATH", JWKS)],
+        ));
+        assert_eq!(
+            errors,
+            [
+                "paths.jti_store is required with auth.jti_max_uses",
+                "paths.credential_issued is required with paths.credential_key",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_auth_settings_are_refused() {
+        let errors = refused(load_with_env(
+            &args(),
+            &[
+                ("JWT_ALGORITHMS", "HS256"),
+                ("PUBLIC_ROUTES", "voting/config"),
+                ("JTI_PRUNE_INTERVAL_SECS", "0"),
+                ("IP_RATE_LIMIT", "fast"),
+                ("COUNTING_STRATEGY", "count_votes_0"),
+                ("USER_HASH_SCHEME", "md5"),
+            ],
+        ));
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+    }
+
+    #[test]
+    fn test_zero_workers_are_refused() {
+        let args = ServerArgs {
+            workers: Some(0),
+            ..args()
+        };
+        assert_eq!(
+            refused(load_with_env(&args, &[])),
+            ["workers must be at least 1"]
+        );
+    }
+
+    #[test]
+    fn test_bad_bind_address_is_refused() {
+        let errors = refused(load_with_env(&args(), &[("BIND_ADDRESS", "localhost")]));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("BIND_ADDRESS=\"localhost\""));
+
+        let config_filepath = toml_filepath("bind", "bind = \"localhost\"\n");
+        let args = ServerArgs {
+            config: Some(config_filepath),
+            ..args()
+        };
+        let errors = refused(load_with_env(&args, &[]));
+        assert!(errors[0].starts_with("Invalid server config"));
+    }
+
+    #[test]
+    fn test_tls_without_a_key_is_refused() {
+        let args = ServerArgs {
+            tls_cert: Some(VOTING_CONFIG.to_string()),
+            ..args()
+        };
+        assert_eq!(
+            refused(load_with_env(&args, &[])),
+            ["tls.key_path is required"]
+        );
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let args = ServerArgs {
+            workers: Some(0),
+            count_queue_capacity: Some(0),
+            cors_origins: vec!["example.com".to_string()],
+            ..args()
+        };
+        assert_eq!(refused(load_with_env(&args, &[])).len(), 3);
+    }
+}
@@ -0,0 +1,132 @@
+// Graceful shutdown. On SIGTERM or SIGINT the server stops taking votes and
+// finishes the requests in flight. Once the last sender is dropped, the
+// workers drain their channels; the Ledger Worker syncs every batch it
+// writes. The final counts are then written to a snapshot file.
+
+use crate::errors::Result;
+use crate::models::VoteCounts;
+use crate::supervisor::WorkerStatus;
+use actix_rt::signal::unix::{signal, SignalKind};
+use actix_web::dev::ServerHandle;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+#[derive(Serialize)]
+struct CountsSnapshotFile<'a> {
+    written_at: i64,
+    #[serde(flatten)]
+    counts: &'a VoteCounts,
+}
+
+// Stops the server on the first SIGTERM or SIGINT. `shutting_down` makes
+// votes that arrive before the server stops get a 503. Returns the shutdown
+// deadline, set `shutdown_timeout` after the signal: stopping the server
+// and draining the workers share that time.
+pub fn spawn_signal_handler(
+    server: ServerHandle,
+    shutting_down: Arc<AtomicBool>,
+    shutdown_timeout: Duration,
+) -> std::io::Result<Arc<OnceLock<Instant>>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let deadline = Arc::new(OnceLock::new());
+    let signal_deadline = deadline.clone();
+    actix_rt::spawn(async move {
+        let name = tokio::select! {
+            _ = sigterm.recv() => "SIGTERM",
+            _ = sigint.recv() => "SIGINT",
+        };
+
+        info!("Received {}; no longer accepting votes", name);
+        let _ = signal_deadline.set(Instant::now() + shutdown_timeout);
+        shutting_down.store(true, Ordering::Release);
+        server.stop(true).await;
+    });
+    Ok(deadline)
+}
+
+// Waits until every worker has drained its channel and stopped, or the
+// timeout passes. Returns whether they all stopped.
+pub async fn drain_workers(workers: &[WorkerStatus], timeout: Duration) -> bool {
+    let drain = async {
+        for worker in workers {
+            worker.wait_stopped().await;
+        }
+    };
+    match tokio::time::timeout(timeout, drain).await {
+        Ok(()) => true,
+        Err(_) => {
+            let running = workers
+                .iter()
+                .filter(|worker| !worker.is_stopped())
+                .map(|worker| worker.name())
+                .collect::<Vec<_>>();
+            error!(
+                "Workers didn't drain within {:?}; queued votes may be lost: {:?}",
+                timeout, running
+            );
+            false
+        }
+    }
+}
+
+// Written aside and renamed so a crash never leaves half a snapshot
+pub fn write_counts_snapshot(filepath: &str, counts: &VoteCounts) -> Result<()> {
+    let snapshot = CountsSnapshotFile {
+        written_at: chrono::Utc::now().timestamp_millis(),
+        counts,
+    };
+    let tmp_filepath = format!("{}.tmp", filepath);
+    let mut tmp = File::create(&tmp_filepath)?;
+    serde_json::to_writer_pretty(&mut tmp, &snapshot)?;
+    tmp.write_all(b"\n")?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_filepath, filepath)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Ballot, LedgerWorkerMsg};
+    use crate::test_support::temp_filepath;
+    use crate::utils::spawn_ledger_worker;
+
+    #[actix_web::test]
+    async fn test_drain_writes_queued_votes_before_returning() {
+        let cl_filepath = temp_filepath("shutdown", "drain", "csv");
+        let vl_filepath = temp_filepath("shutdown", "drain_vl", "csv");
+        let (sender, ledger_worker) = spawn_ledger_worker(&cl_filepath, &vl_filepath, 64).await;
+        for i in 0..50 {
+            let ballot = Ballot {
+                vote_id: format!("vote-{}", i),
+                user_id_hash: format!("{:032x}", i),
+                timestamp: i,
+                choice: "A".to_string(),
+            };
+            sender.send(LedgerWorkerMsg::from(&ballot)).await.unwrap();
+        }
+
+        // As when the server stops: the last sender goes with it
+        drop(sender);
+        assert!(drain_workers(std::slice::from_ref(&ledger_worker), Duration::from_secs(5)).await);
+        assert!(ledger_worker.is_stopped());
+        let vl = fs::read_to_string(&vl_filepath).unwrap();
+        assert_eq!(vl.lines().count(), 50);
+        assert_eq!(vl.lines().last(), Some("vote-49,A"));
+    }
+
+    #[actix_web::test]
+    async fn test_drain_gives_up_at_the_timeout() {
+        let cl_filepath = temp_filepath("shutdown", "timeout", "csv");
+        let vl_filepath = temp_filepath("shutdown", "timeout_vl", "csv");
+        // The sender is kept, so the worker never stops
+        let (_sender, ledger_worker) = spawn_ledger_worker(&cl_filepath, &vl_filepath, 64).await;
+        assert!(!drain_workers(&[ledger_worker], Duration::from_millis(50)).await);
+    }
+}
@@ -0,0 +1,177 @@
+// Restarts workers that fail instead of letting one error take their
+// channel down with them.
+//
+// The worker's receiver is owned by the supervisor and lent to each run,
+// so messages queued while a worker restarts are handled by the next run.
+// A run that returns an error or panics is restarted with backoff; one
+// that returns Ok has seen its channel close and isn't restarted.
+
+use crate::errors::Result;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{Mutex, Notify, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+const MIN_RESTART_DELAY: Duration = Duration::from_millis(100);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(10);
+
+// Whether a worker is running and has recovered its state
+#[derive(Clone)]
+pub struct WorkerStatus {
+    name: Arc<str>,
+    up: Arc<AtomicBool>,
+    restarts: Arc<AtomicU64>,
+    // Set once the worker's channel has closed and it has finished
+    stopped: Arc<AtomicBool>,
+    stopped_notify: Arc<Notify>,
+}
+
+impl WorkerStatus {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            up: Arc::new(AtomicBool::new(false)),
+            restarts: Arc::new(AtomicU64::new(0)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            stopped_notify: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::Acquire)
+    }
+
+    // Called by the worker once it's ready for messages
+    pub fn set_up(&self) {
+        self.up.store(true, Ordering::Release);
+    }
+
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    // Resolves once the worker has handled every message and stopped
+    pub async fn wait_stopped(&self) {
+        let notified = self.stopped_notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.stopped.load(Ordering::Acquire) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+// Runs `run` with the receiver until it returns Ok, restarting it after
+// errors and panics
+pub fn supervise<M, F, Fut>(status: WorkerStatus, rx: Receiver<M>, mut run: F) -> JoinHandle<()>
+where
+    M: Send + 'static,
+    F: FnMut(OwnedMutexGuard<Receiver<M>>, WorkerStatus) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let rx = Arc::new(Mutex::new(rx));
+    tokio::spawn(async move {
+        let mut delay = MIN_RESTART_DELAY;
+        loop {
+            let guard = rx.clone().lock_owned().await;
+            // Spawned so a panic only ends this run; the guard is released
+            // while unwinding and the receiver stays with the supervisor
+            let result = tokio::spawn(run(guard, status.clone())).await;
+            let was_up = status.up.swap(false, Ordering::AcqRel);
+
+            match result {
+                Ok(Ok(())) => {
+                    info!("{} stopped", status.name);
+                    status.stopped.store(true, Ordering::Release);
+                    status.stopped_notify.notify_waiters();
+                    return;
+                }
+                Ok(Err(err)) => error!("{} failed: {}", status.name, err),
+                Err(err) => error!("{} panicked: {}", status.name, err),
+            }
+
+            // Back off while the worker keeps failing before it's ready
+            if was_up {
+                delay = MIN_RESTART_DELAY;
+            }
+            info!("Restarting {} in {:?}", status.name, delay);
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RESTART_DELAY);
+            status.restarts.fetch_add(1, Ordering::Relaxed);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+    use std::sync::atomic::AtomicU32;
+
+    #[actix_web::test]
+    async fn test_failed_runs_are_restarted_without_losing_messages() {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        for i in 0..3 {
+            tx.send(i).await.unwrap();
+        }
+
+        let runs = Arc::new(AtomicU32::new(0));
+        let handled = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let status = WorkerStatus::new("Test Worker");
+        let (worker_runs, worker_handled) = (runs.clone(), handled.clone());
+        supervise(status.clone(), rx, move |mut rx, status| {
+            let run = worker_runs.fetch_add(1, Ordering::Relaxed);
+            let handled = worker_handled.clone();
+            async move {
+                match run {
+                    // Takes a message with it
+                    0 => {
+                        let msg = rx.recv().await.unwrap();
+                        handled.lock().unwrap().push(msg);
+                        Err(AppError::InternalError {
+                            title: "Test".to_string(),
+                            message: "failed".to_string(),
+                        })
+                    }
+                    1 => panic!("Test worker panicked"),
+                    _ => {
+                        status.set_up();
+                        while let Some(msg) = rx.recv().await {
+                            handled.lock().unwrap().push(msg);
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        });
+
+        // Queued while the worker restarts
+        tx.send(3).await.unwrap();
+        while !status.is_up() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(status.restarts(), 2);
+        assert!(!status.is_stopped());
+
+        drop(tx);
+        tokio::time::timeout(Duration::from_secs(1), status.wait_stopped())
+            .await
+            .unwrap();
+        assert!(!status.is_up());
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        assert_eq!(*handled.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,188 @@
+// Logs and traces. Events are written to stdout as text or JSON lines, with
+// the fields of the spans they happened in, including each request's
+// `request_id`. Spans are also exported over OTLP/HTTP when an endpoint is
+// configured.
+
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::runtime::TokioCurrentThread;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use std::io::IsTerminal;
+use tracing::error;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+const SERVICE_NAME: &str = "voterium_backend";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// Parsed and built when the configuration is loaded, so a bad filter or
+// endpoint is reported as invalid configuration
+pub struct TelemetryConfig {
+    // Which events are logged
+    pub log_filter: EnvFilter,
+    pub log_format: LogFormat,
+    // Also log when spans close, with how long they were busy
+    pub log_span_events: bool,
+    pub otlp: Option<OtlpConfig>,
+}
+
+pub struct OtlpConfig {
+    pub tracer_provider: TracerProvider,
+    // Which spans are exported
+    pub filter: EnvFilter,
+}
+
+// Flushes the exported spans when dropped
+pub struct Telemetry {
+    tracer_provider: Option<TracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(err) = tracer_provider.shutdown() {
+                error!("Failed to flush the exported spans: {}", err);
+            }
+        }
+    }
+}
+
+// Exports spans in batches to the OTLP/HTTP collector at `endpoint`
+pub fn otlp_tracer_provider(endpoint: &str) -> Result<TracerProvider, TraceError> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    // The server's runtimes are single threaded, so the exporter gets a
+    // thread of its own
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, TokioCurrentThread)
+        .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)]))
+        .build())
+}
+
+// Installs the global subscriber. Records from the `log` crate, which
+// actix-web uses, are forwarded to it.
+pub fn init_telemetry(config: TelemetryConfig) -> Telemetry {
+    let log_filter = config.log_filter;
+    let span_events = if config.log_span_events {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    let fmt_layer = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(std::io::stdout().is_terminal())
+            .with_span_events(span_events)
+            .with_filter(log_filter)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_span_events(span_events)
+            .with_filter(log_filter)
+            .boxed(),
+    };
+
+    let mut tracer_provider = None;
+    let otel_layer = config.otlp.map(|otlp| {
+        let tracer = otlp.tracer_provider.tracer(SERVICE_NAME);
+        // Continues traces started by callers that send a `traceparent`
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        opentelemetry::global::set_tracer_provider(otlp.tracer_provider.clone());
+        tracer_provider = Some(otlp.tracer_provider);
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(otlp.filter)
+    });
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+
+    Telemetry { tracer_provider }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tracing_subscriber::Registry;
+
+    // Stands in for an OTLP/HTTP collector: accepts one export and sends
+    // back the request path and body
+    fn spawn_collector() -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/x-protobuf\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            tx.send((path.to_string(), body)).unwrap();
+        });
+        (endpoint, rx)
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_spans_are_exported_over_otlp() {
+        let (endpoint, exports) = spawn_collector();
+        let tracer_provider = otlp_tracer_provider(&format!("{}/", endpoint)).unwrap();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(SERVICE_NAME)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("send_ballot").entered();
+        });
+        // Flushes the batch
+        tracer_provider.shutdown().unwrap();
+
+        let (path, body) = exports.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(path, "/v1/traces");
+        // Protobuf keeps strings as they are
+        assert!(contains(&body, SERVICE_NAME.as_bytes()));
+        assert!(contains(&body, b"send_ballot"));
+    }
+}
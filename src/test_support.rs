@@ -0,0 +1,44 @@
+// Fixtures shared by the unit tests
+
+use crate::hash_schemes::HashScheme;
+use crate::peppers::Peppers;
+use std::sync::Mutex;
+use std::{env, fs};
+
+// The environment is shared by every test thread
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+// A path in the temp directory for the `module` test `name`, with nothing
+// left there from an earlier run. The process id keeps concurrent runs
+// apart.
+pub fn temp_filepath(module: &str, name: &str, extension: &str) -> String {
+    let filepath = std::env::temp_dir().join(format!(
+        "voterium_{}_{}_{}.{}",
+        module,
+        name,
+        std::process::id(),
+        extension
+    ));
+    let _ = fs::remove_file(&filepath);
+    filepath.to_string_lossy().into_owned()
+}
+
+// Runs `f` with `vars` set in the environment while it's locked
+pub fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    let _lock = ENV_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (name, value) in vars {
+        env::set_var(name, value);
+    }
+    let result = f();
+    for (name, _) in vars {
+        env::remove_var(name);
+    }
+    result
+}
+
+// A single all-zero pepper, as the only BACKEND_SALT
+pub fn peppers() -> Peppers {
+    Peppers::single(vec![0; 8], HashScheme::Blake2b)
+}
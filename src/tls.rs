@@ -0,0 +1,406 @@
+// HTTPS with rustls. The certificate is served through a resolver so a
+// renewed one can be swapped in without a restart. With a client CA
+// configured, clients may present a certificate signed by it, and the admin
+// routes require one.
+
+use crate::errors::{AppError, ErrorCode, Result};
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{Extensions, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, uri::Authority};
+use actix_web::middleware::Next;
+use actix_web::rt::net::TcpStream;
+use actix_web::{web, Error, HttpRequest, HttpResponse, ResponseError};
+use arc_swap::ArcSwap;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::any::Any;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tracing::warn;
+
+// The certificate and key currently served. A reload swaps in a new pair;
+// connections already open keep the one they were made with.
+pub struct CertStore {
+    key: ArcSwap<CertifiedKey>,
+}
+
+impl CertStore {
+    pub fn new(key: CertifiedKey) -> Self {
+        Self {
+            key: ArcSwap::from_pointee(key),
+        }
+    }
+
+    pub fn replace(&self, key: CertifiedKey) {
+        self.key.store(Arc::new(key));
+    }
+}
+
+impl fmt::Debug for CertStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertStore").finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.load_full())
+    }
+}
+
+// Set on connections whose client presented a certificate that chains to the
+// client CA; rustls refuses the handshake for any other certificate
+#[derive(Clone)]
+pub struct ClientCertificate {
+    pub der: CertificateDer<'static>,
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn tls_error(message: String) -> AppError {
+    AppError::InternalError {
+        title: "TLS error".to_string(),
+        message,
+    }
+}
+
+fn read_certs(filepath: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(filepath)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(tls_error(format!("No certificates in {}", filepath)));
+    }
+    Ok(certs)
+}
+
+// The certificate chain and its private key, checked to belong together so
+// a half-written renewal isn't served
+pub fn load_certified_key(cert_filepath: &str, key_filepath: &str) -> Result<CertifiedKey> {
+    let certs = read_certs(cert_filepath)?;
+    let mut reader = BufReader::new(File::open(key_filepath)?);
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| tls_error(format!("No private key in {}", key_filepath)))?;
+    Ok(CertifiedKey::from_der(certs, key, &provider())?)
+}
+
+pub fn load_client_roots(ca_filepath: &str) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca_filepath)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+pub fn server_config(
+    cert_store: Arc<CertStore>,
+    client_roots: Option<RootCertStore>,
+) -> Result<ServerConfig> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    let builder = match client_roots {
+        // Clients without a certificate can still connect; only the admin
+        // routes need one
+        Some(roots) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+                .allow_unauthenticated()
+                .build()
+                .map_err(|err| tls_error(format!("Invalid client CA: {}", err)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(builder.with_cert_resolver(cert_store))
+}
+
+// Passed to `HttpServer::on_connect`; makes the client's certificate
+// available to requests as connection data
+pub fn on_connect(conn: &dyn Any, extensions: &mut Extensions) {
+    let Some(stream) = conn.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let (_, connection) = stream.get_ref();
+    if let Some(der) = connection
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+    {
+        extensions.insert(ClientCertificate {
+            der: der.clone().into_owned(),
+        });
+    }
+}
+
+pub async fn require_client_certificate<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> core::result::Result<ServiceResponse<EitherBody<B, BoxBody>>, Error>
+where
+    B: MessageBody + 'static,
+{
+    if req.conn_data::<ClientCertificate>().is_some() {
+        let res = next.call(req).await?;
+        return Ok(res.map_into_left_body());
+    }
+
+    warn!(
+        "Refused {} {} without a client certificate",
+        req.method(),
+        req.path()
+    );
+    let response = AppError::Forbidden {
+        code: ErrorCode::Forbidden,
+        message: "Requires a client certificate".to_string(),
+    }
+    .error_response();
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+// `Strict-Transport-Security` value
+pub fn hsts_header(max_age_secs: u64, include_subdomains: bool) -> String {
+    if include_subdomains {
+        format!("max-age={}; includeSubDomains", max_age_secs)
+    } else {
+        format!("max-age={}", max_age_secs)
+    }
+}
+
+// Default service of the plain HTTP listener: the same URL on the HTTPS
+// port. 308 keeps the method and body of votes sent to the wrong scheme.
+// The host comes from the Host header alone: X-Forwarded-Host, which
+// anyone can send, would make this an open redirect.
+pub async fn redirect_to_https(req: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let authority = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .filter(|authority| !authority.as_str().contains('@'));
+    let Some(authority) = authority else {
+        return AppError::BadRequest {
+            code: ErrorCode::InvalidRequest,
+            message: "Missing or invalid Host header".to_string(),
+        }
+        .error_response();
+    };
+    // Without the port; IPv6 literals such as [::1] keep their brackets
+    let host = authority.host();
+    let authority = match **https_port {
+        443 => host.to_string(),
+        port => format!("{}:{}", host, port),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("https://{}{}", authority, path)))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ReloadStatus;
+    use crate::test_support::temp_filepath;
+    use crate::utils::spawn_cert_reloader;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{http::StatusCode, App, HttpServer};
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+    use std::fs;
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    // A test CA, a server certificate for localhost and a client
+    // certificate, both signed by the CA
+    const CA: &str = "examples/tls/ca.pem";
+    const SERVER_CERT: &str = "examples/tls/server.pem";
+    const SERVER_KEY: &str = "examples/tls/server.key";
+    const CLIENT_CERT: &str = "examples/tls/client.pem";
+    const CLIENT_KEY: &str = "examples/tls/client.key";
+
+    // The Location of the redirect for a request with these headers, or
+    // the status when there is none
+    async fn redirect(
+        https_port: u16,
+        headers: &[(&str, &str)],
+    ) -> std::result::Result<String, StatusCode> {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(https_port))
+                .default_service(web::to(redirect_to_https)),
+        )
+        .await;
+        let mut req = TestRequest::post().uri("/voting/vote?dry_run=1");
+        for header in headers {
+            req = req.insert_header(*header);
+        }
+        let res = call_service(&app, req.to_request()).await;
+        match res.headers().get(header::LOCATION) {
+            Some(location) => {
+                assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+                Ok(location.to_str().unwrap().to_string())
+            }
+            None => Err(res.status()),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_redirect_keeps_the_host_path_and_query() {
+        assert_eq!(
+            redirect(8443, &[("host", "vote.example.com:8080")]).await,
+            Ok("https://vote.example.com:8443/voting/vote?dry_run=1".to_string())
+        );
+        assert_eq!(
+            redirect(443, &[("host", "vote.example.com")]).await,
+            Ok("https://vote.example.com/voting/vote?dry_run=1".to_string())
+        );
+        assert_eq!(
+            redirect(8443, &[("host", "[::1]:8080")]).await,
+            Ok("https://[::1]:8443/voting/vote?dry_run=1".to_string())
+        );
+        assert_eq!(
+            redirect(443, &[("host", "[::1]")]).await,
+            Ok("https://[::1]/voting/vote?dry_run=1".to_string())
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_redirect_ignores_forwarded_hosts() {
+        let headers = [
+            ("host", "vote.example.com"),
+            ("x-forwarded-host", "evil.example.com"),
+            ("forwarded", "host=evil.example.com"),
+        ];
+        assert_eq!(
+            redirect(443, &headers).await,
+            Ok("https://vote.example.com/voting/vote?dry_run=1".to_string())
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_redirect_needs_a_valid_host() {
+        assert_eq!(
+            redirect(443, &[("x-forwarded-host", "evil.example.com")]).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        for host in ["evil.example.com/path", "vote.example.com@evil.example.com"] {
+            assert_eq!(
+                redirect(443, &[("host", host)]).await,
+                Err(StatusCode::BAD_REQUEST),
+                "{}",
+                host
+            );
+        }
+    }
+
+    fn client_config(client_cert: bool) -> ClientConfig {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(load_client_roots(CA).unwrap());
+        if !client_cert {
+            return builder.with_no_client_auth();
+        }
+        let certs = read_certs(CLIENT_CERT).unwrap();
+        let mut reader = BufReader::new(File::open(CLIENT_KEY).unwrap());
+        let key = rustls_pemfile::private_key(&mut reader).unwrap().unwrap();
+        builder.with_client_auth_cert(certs, key).unwrap()
+    }
+
+    // The status line of GET / over a TLS connection to `port`
+    fn get_status_line(port: u16, client_cert: bool) -> String {
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let connection = ClientConnection::new(Arc::new(client_config(client_cert)), server_name);
+        let socket = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = StreamOwned::new(connection.unwrap(), socket);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).unwrap();
+        status_line.trim_end().to_string()
+    }
+
+    #[actix_web::test]
+    async fn test_client_certificate_is_required() {
+        let cert_store = CertStore::new(load_certified_key(SERVER_CERT, SERVER_KEY).unwrap());
+        let config =
+            server_config(Arc::new(cert_store), Some(load_client_roots(CA).unwrap())).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = HttpServer::new(|| {
+            App::new()
+                .wrap(from_fn(require_client_certificate))
+                .route("/", web::get().to(HttpResponse::Ok))
+        })
+        .on_connect(on_connect)
+        .workers(1)
+        .disable_signals()
+        .listen_rustls_0_23(listener, config)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let with_cert = web::block(move || get_status_line(port, true)).await;
+        let without_cert = web::block(move || get_status_line(port, false)).await;
+        handle.stop(false).await;
+        assert_eq!(with_cert.unwrap(), "HTTP/1.1 200 OK");
+        assert_eq!(without_cert.unwrap(), "HTTP/1.1 403 Forbidden");
+    }
+
+    fn served_cert(cert_store: &CertStore) -> CertificateDer<'static> {
+        cert_store.key.load().cert[0].clone()
+    }
+
+    #[actix_web::test]
+    async fn test_renewed_certificate_is_served() {
+        let cert_filepath = temp_filepath("tls", "reload", "pem");
+        let key_filepath = temp_filepath("tls", "reload", "key");
+        fs::copy(SERVER_CERT, &cert_filepath).unwrap();
+        fs::copy(SERVER_KEY, &key_filepath).unwrap();
+        let cert_store = Arc::new(CertStore::new(
+            load_certified_key(&cert_filepath, &key_filepath).unwrap(),
+        ));
+        let reload_status = ReloadStatus::default();
+        spawn_cert_reloader(
+            cert_store.clone(),
+            cert_filepath.clone(),
+            key_filepath.clone(),
+            Duration::from_millis(10),
+            reload_status.clone(),
+        );
+        let client_cert = read_certs(CLIENT_CERT).unwrap().remove(0);
+
+        // Half a renewal: the new certificate with the old key is ignored
+        actix_web::rt::time::sleep(Duration::from_millis(30)).await;
+        fs::copy(CLIENT_CERT, &cert_filepath).unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            served_cert(&cert_store),
+            read_certs(SERVER_CERT).unwrap()[0]
+        );
+        assert!(!reload_status.is_valid());
+
+        fs::copy(CLIENT_KEY, &key_filepath).unwrap();
+        for _ in 0..100 {
+            if served_cert(&cert_store) == client_cert {
+                break;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(served_cert(&cert_store), client_cert);
+        assert!(reload_status.is_valid());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::errors::{AppError, ErrorCode, Result};
+use actix_web::web;
+use rustc_hash::FxHashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+struct TokenUse {
+    // Counted once committed, before they're written to the log
+    uses: u32,
+    // Committed uses still being appended to the log, which a prune leaves
+    // for the append to write
+    unlogged: u32,
+    // Held by requests that haven't finished, see TokenUseReservation
+    reserved: u32,
+    // The token's `exp` plus the JWT leeway, after which it can't be
+    // presented again and its entry can be pruned
+    expires_at: i64,
+}
+
+// Counts how many votes each token id (`jti`) has submitted. Every use is
+// appended to a log file as a `jti,expires_at` line and synced, so the
+// counts survive restarts. Pruning drops expired tokens and rewrites the log.
+//
+// The log has its own lock so reservations never wait on a sync. A use is
+// counted as soon as it's committed and appended after; uses still being
+// appended are left out of a prune's rewrite, so each is logged once.
+#[derive(Clone)]
+pub struct TokenUses {
+    max_uses: u32,
+    filepath: String,
+    uses: Arc<Mutex<FxHashMap<String, TokenUse>>>,
+    log: Arc<Mutex<File>>,
+}
+
+impl TokenUses {
+    pub fn open(filepath: &str, max_uses: u32, now: i64) -> Result<Self> {
+        let mut uses: FxHashMap<String, TokenUse> = FxHashMap::default();
+        let contents = match fs::read_to_string(filepath) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        for line in contents.lines() {
+            let parsed = line
+                .rsplit_once(',')
+                .and_then(|(jti, expires_at)| Some((jti, expires_at.parse::<i64>().ok()?)));
+            let Some((jti, expires_at)) = parsed else {
+                warn!(
+                    "Skipping malformed line in token use log {}: {:?}",
+                    filepath, line
+                );
+                continue;
+            };
+            if expires_at < now {
+                continue;
+            }
+            uses.entry(jti.to_string())
+                .or_insert(TokenUse {
+                    uses: 0,
+                    unlogged: 0,
+                    reserved: 0,
+                    expires_at,
+                })
+                .uses += 1;
+        }
+        info!(
+            "Loaded {} unexpired token ids from {}",
+            uses.len(),
+            filepath
+        );
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filepath)?;
+        let token_uses = Self {
+            max_uses,
+            filepath: filepath.to_string(),
+            uses: Arc::new(Mutex::new(uses)),
+            log: Arc::new(Mutex::new(log)),
+        };
+        // Drop the expired lines left over from the last run
+        token_uses.prune(now)?;
+        Ok(token_uses)
+    }
+
+    // Holds one of the token's uses, or fails if it has none left. The use
+    // is only written to the log when the reservation is committed, once
+    // whatever it was for has succeeded; dropping it gives the use back.
+    pub fn reserve_use(&self, jti: &str, expires_at: i64) -> Result<TokenUseReservation> {
+        // One line per use, so the id can't contain a newline
+        if jti.is_empty() || jti.contains(['\n', '\r']) {
+            return Err(AppError::AuthError {
+                code: ErrorCode::Unauthorized,
+                message: "Invalid token id".to_string(),
+            });
+        }
+
+        let mut uses = self.uses.lock().expect("Token uses lock poisoned");
+        let token_use = uses.entry(jti.to_string()).or_insert(TokenUse {
+            uses: 0,
+            unlogged: 0,
+            reserved: 0,
+            expires_at,
+        });
+        if token_use.uses + token_use.reserved >= self.max_uses {
+            return Err(AppError::AuthError {
+                code: ErrorCode::TokenUsed,
+                message: "Token has already been used".to_string(),
+            });
+        }
+        token_use.reserved += 1;
+
+        Ok(TokenUseReservation {
+            token_uses: self.clone(),
+            jti: jti.to_string(),
+            committed: false,
+        })
+    }
+
+    // Counts a reserved use as used, even if the log can't be written, as
+    // whatever the use was for has already happened. Returns its expiry.
+    fn commit_use(&self, jti: &str) -> i64 {
+        let mut uses = self.uses.lock().expect("Token uses lock poisoned");
+        let token_use = uses.get_mut(jti).expect("Reserved token use is missing");
+        token_use.reserved -= 1;
+        token_use.uses += 1;
+        token_use.unlogged += 1;
+        token_use.expires_at
+    }
+
+    fn release_use(&self, jti: &str) {
+        let mut uses = self.uses.lock().expect("Token uses lock poisoned");
+        if let Some(token_use) = uses.get_mut(jti) {
+            token_use.reserved -= 1;
+            if token_use.uses == 0 && token_use.reserved == 0 {
+                uses.remove(jti);
+            }
+        }
+    }
+
+    // Forgets tokens that have expired and compacts the log to the
+    // remaining uses. Blocks on the rewrite, so outside of startup it's run
+    // on the blocking thread pool.
+    pub fn prune(&self, now: i64) -> Result<usize> {
+        let mut log = self.log.lock().expect("Token use log lock poisoned");
+        let (pruned, lines) = {
+            let mut uses = self.uses.lock().expect("Token uses lock poisoned");
+            let before = uses.len();
+            uses.retain(|_, token_use| {
+                token_use.expires_at >= now || token_use.reserved > 0 || token_use.unlogged > 0
+            });
+            let mut lines = String::new();
+            for (jti, token_use) in uses.iter() {
+                for _ in 0..token_use.uses - token_use.unlogged {
+                    lines.push_str(&format!("{},{}\n", jti, token_use.expires_at));
+                }
+            }
+            (before - uses.len(), lines)
+        };
+
+        let tmp_filepath = format!("{}.tmp", self.filepath);
+        let mut tmp = File::create(&tmp_filepath)?;
+        tmp.write_all(lines.as_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_filepath, &self.filepath)?;
+        *log = OpenOptions::new().append(true).open(&self.filepath)?;
+
+        Ok(pruned)
+    }
+}
+
+// One use of a token held by a request in progress
+pub struct TokenUseReservation {
+    token_uses: TokenUses,
+    jti: String,
+    committed: bool,
+}
+
+impl TokenUseReservation {
+    // Counts the use, then writes it to the log and syncs it, off the async
+    // worker thread
+    pub async fn commit(mut self) -> Result<()> {
+        self.committed = true;
+        let jti = std::mem::take(&mut self.jti);
+        let expires_at = self.token_uses.commit_use(&jti);
+        let unlogged = UnloggedUse {
+            token_uses: self.token_uses.clone(),
+            jti,
+            expires_at,
+        };
+        web::block(move || unlogged.write())
+            .await
+            .map_err(|err| AppError::InternalError {
+                title: "Token use logging failed".to_string(),
+                message: err.to_string(),
+            })?
+    }
+}
+
+impl Drop for TokenUseReservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.token_uses.release_use(&self.jti);
+        }
+    }
+}
+
+// A committed use that hasn't been appended to the log yet. Dropping it,
+// whether the append succeeded, failed or never ran, hands the use back to
+// prune's rewrite, which then logs a use the append missed.
+struct UnloggedUse {
+    token_uses: TokenUses,
+    jti: String,
+    expires_at: i64,
+}
+
+impl UnloggedUse {
+    // Blocks on the sync, so it's run on the blocking thread pool
+    fn write(self) -> Result<()> {
+        let log = self.token_uses.log.clone();
+        let mut log = log.lock().expect("Token use log lock poisoned");
+        let written =
+            writeln!(log, "{},{}", self.jti, self.expires_at).and_then(|_| log.sync_data());
+        // Before the log is unlocked, so a prune can't write the use as well
+        drop(self);
+        Ok(written?)
+    }
+}
+
+impl Drop for UnloggedUse {
+    fn drop(&mut self) {
+        let mut uses = self
+            .token_uses
+            .uses
+            .lock()
+            .expect("Token uses lock poisoned");
+        if let Some(token_use) = uses.get_mut(&self.jti) {
+            token_use.unlogged -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_filepath;
+
+    fn log_filepath(name: &str) -> String {
+        temp_filepath("token_uses", name, "log")
+    }
+
+    #[actix_web::test]
+    async fn test_dropped_reservation_gives_the_use_back() -> Result<()> {
+        let token_uses = TokenUses::open(&log_filepath("dropped"), 1, 0)?;
+
+        let reservation = token_uses.reserve_use("jti-1", 100)?;
+        // A held use counts against the limit
+        assert!(token_uses.reserve_use("jti-1", 100).is_err());
+        drop(reservation);
+
+        token_uses.reserve_use("jti-1", 100)?.commit().await?;
+        assert!(token_uses.reserve_use("jti-1", 100).is_err());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_only_committed_uses_survive_a_restart() -> Result<()> {
+        let filepath = log_filepath("restart");
+        let token_uses = TokenUses::open(&filepath, 2, 0)?;
+        token_uses.reserve_use("jti-1", 100)?.commit().await?;
+        let _held = token_uses.reserve_use("jti-1", 100)?;
+
+        let reopened = TokenUses::open(&filepath, 2, 0)?;
+        reopened.reserve_use("jti-1", 100)?.commit().await?;
+        assert!(reopened.reserve_use("jti-1", 100).is_err());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_prune_keeps_held_uses_of_expired_tokens() -> Result<()> {
+        let filepath = log_filepath("prune");
+        let token_uses = TokenUses::open(&filepath, 1, 0)?;
+        let reservation = token_uses.reserve_use("jti-1", 100)?;
+
+        assert_eq!(token_uses.prune(200)?, 0);
+        reservation.commit().await?;
+        assert_eq!(fs::read_to_string(&filepath)?, "jti-1,100\n");
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_failed_commit_still_counts_the_use() -> Result<()> {
+        let filepath = log_filepath("commit_error");
+        let token_uses = TokenUses::open(&filepath, 2, 0)?;
+        // Appends fail on a read only handle
+        *token_uses.log.lock().unwrap() = File::open(&filepath)?;
+
+        let reservation = token_uses.reserve_use("jti-1", 100)?;
+        assert!(reservation.commit().await.is_err());
+        // Used rather than still held, so one use is left
+        let _held = token_uses.reserve_use("jti-1", 100)?;
+        assert!(token_uses.reserve_use("jti-1", 100).is_err());
+
+        // The next prune writes the use the append missed
+        token_uses.prune(0)?;
+        assert_eq!(fs::read_to_string(&filepath)?, "jti-1,100\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reservations_dont_wait_for_the_log() -> Result<()> {
+        let token_uses = TokenUses::open(&log_filepath("log_lock"), 1, 0)?;
+        // As while a commit is syncing
+        let _log = token_uses.log.lock().unwrap();
+        drop(token_uses.reserve_use("jti-1", 100)?);
+        Ok(())
+    }
+}
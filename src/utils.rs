@@ -1,22 +1,47 @@
 use std::{
-    env,
-    fs::{self, File},
-    io::Read,
+    env, fs,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
+use actix_web::web;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use blake2::{digest::consts::U12, Blake2b, Digest};
-use jsonwebtoken::DecodingKey;
+use jsonwebtoken::{Algorithm, DecodingKey};
 use rand::{rngs::OsRng, RngCore};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, RsaPrivateKey};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
 use crate::{
+    auth::{self, PublicRoute},
+    counting::strategies::{self, CountingStrategy},
+    credentials::{CredentialIssuer, IssuedCredentials},
     errors::Result,
-    models::{Choice, Config, CountWorkerMsg, LedgerWorkerMsg},
+    hash_schemes::HashScheme,
+    jwks::{parse_jwks, KeyStore, VerificationKey},
+    ledgers::load_current_cl,
+    metrics::Metrics,
+    models::{
+        Choice, Config, CountWorkerShard, CountWorkers, CountsSnapshot, JwtConfig, LedgerWorkerMsg,
+        ReloadStatus, RequiredClaim,
+    },
+    peppers::{Pepper, Peppers},
+    rate_limits::{RateLimiter, RateLimits},
+    server_config::{AuthConfig, ConfigError, PathConfig, RateLimitConfig, TlsConfig},
+    supervisor::{supervise, WorkerStatus},
+    telemetry::{otlp_tracer_provider, LogFormat, OtlpConfig, TelemetryConfig},
+    tls::{self, CertStore},
+    token_uses::TokenUses,
     workers::{run_counts_worker, run_ledger_worker},
 };
 
 type Blake2b96 = Blake2b<U12>; // 96 bytes = 12 * 8 bits
 
+// The loaders below read the environment and files at startup; their
+// errors are reported before anything starts
+type ConfigResult<T> = std::result::Result<T, ConfigError>;
+
 pub fn hash_user_id(user_id: &str, user_salt: &str, backend_salt_bytes: &[u8]) -> Result<String> {
     // Combine user_id with user_salt and backend_salt.
     // user_salt and backend_salt should be 8 bytes each.
@@ -27,11 +52,11 @@ pub fn hash_user_id(user_id: &str, user_salt: &str, backend_salt_bytes: &[u8]) -
 
     hasher.update(user_id.as_bytes());
     hasher.update(&user_salt);
-    hasher.update(&backend_salt_bytes);
+    hasher.update(backend_salt_bytes);
 
     let result = hasher.finalize();
 
-    let user_id_hash = URL_SAFE_NO_PAD.encode(&result);
+    let user_id_hash = URL_SAFE_NO_PAD.encode(result);
 
     Ok(user_id_hash)
 }
@@ -42,92 +67,638 @@ pub fn gen_random_b64_string(length: usize) -> String {
     URL_SAFE_NO_PAD.encode(&random_bytes)
 }
 
-pub fn load_voting_config(filepath: &str) -> Config {
-    let mut file = File::open(filepath).expect("Failed to open voting_config.json");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Failed to read voting_config.json");
-    let config: Config =
-        serde_json::from_str(&contents).expect("Failed to parse voting_config.json");
+pub fn load_voting_config(filepath: &str) -> ConfigResult<Config> {
+    let contents = fs::read_to_string(filepath)
+        .map_err(|err| format!("Failed to read voting config {}: {}", filepath, err))?;
+    let config: Config = serde_json::from_str(&contents)
+        .map_err(|err| format!("Failed to parse voting config {}: {}", filepath, err))?;
 
-    validate_unique_choice_keys(&config.choices);
-    config
+    check_choices(&config.choices)?;
+    Ok(config)
 }
 
-pub fn validate_unique_choice_keys(choices: &[Choice]) -> () {
+// The problem with the configured choices, if any
+pub fn check_choices(choices: &[Choice]) -> std::result::Result<(), String> {
+    if choices.is_empty() {
+        return Err("There must be at least one choice".to_string());
+    }
+
     let mut seen_keys = std::collections::HashSet::new();
     for choice in choices {
-        if choice.key.len() < 1 {
-            panic!("Choice key must not be empty");
+        if choice.key.is_empty() {
+            return Err("Choice key must not be empty".to_string());
         }
 
         if !seen_keys.insert(choice.key_u8()) {
-            panic!("First character of choice key must be unique");
+            return Err("First character of choice key must be unique".to_string());
         }
     }
+    Ok(())
+}
+
+// Reads a secret from the environment variable `name`, or from the file
+// named by `<name>_FILE`
+pub fn load_secret(name: &str) -> ConfigResult<Option<String>> {
+    if let Ok(value) = env::var(name) {
+        return Ok(Some(value));
+    }
+    let Ok(filepath) = env::var(format!("{}_FILE", name)) else {
+        return Ok(None);
+    };
+    let value = fs::read_to_string(&filepath)
+        .map_err(|err| format!("Failed to read {}_FILE {}: {}", name, filepath, err))?;
+    Ok(Some(value.trim().to_string()))
 }
 
-pub fn load_backend_salt() -> Vec<u8> {
-    // Get the backend salt from the environment variable
-    let backend_salt = env::var("BACKEND_SALT").expect("BACKEND_SALT must be set");
+// BACKEND_PEPPERS is a comma separated list of `version:base64` peppers.
+// Without it, BACKEND_SALT is the only pepper, as version 1.
+pub fn load_peppers(hash_scheme: &str) -> ConfigResult<Peppers> {
+    let peppers = match load_secret("BACKEND_PEPPERS")? {
+        Some(peppers) => peppers
+            .split(',')
+            .filter(|pepper| !pepper.trim().is_empty())
+            .map(|pepper| {
+                let (version, secret) = pepper
+                    .trim()
+                    .split_once(':')
+                    .ok_or("Invalid BACKEND_PEPPERS; entries must be version:base64".to_string())?;
+                let secret = URL_SAFE_NO_PAD
+                    .decode(secret)
+                    .map_err(|_| "Invalid BACKEND_PEPPERS; peppers must be valid Base64")?;
+                if secret.len() < 8 {
+                    return Err("Peppers must be at least 8 bytes long".into());
+                }
+                Ok(Pepper {
+                    version: version
+                        .parse()
+                        .map_err(|_| "Invalid BACKEND_PEPPERS; versions must be numbers")?,
+                    secret,
+                })
+            })
+            .collect::<std::result::Result<_, String>>()?,
+        None => Vec::new(),
+    };
+
+    let scheme = HashScheme::parse(hash_scheme)
+        .ok_or_else(|| format!("Invalid user_hash_scheme {:?}", hash_scheme))?;
+    let peppers = if peppers.is_empty() {
+        Peppers::single(load_backend_salt()?, scheme)
+    } else {
+        Peppers::new(peppers, scheme).map_err(|err| format!("Invalid BACKEND_PEPPERS: {}", err))?
+    };
+    info!(
+        "Using {} user_id hashes with pepper version {}",
+        peppers.scheme().id(),
+        peppers.current_version()
+    );
+    Ok(peppers)
+}
+
+pub fn load_backend_salt() -> ConfigResult<Vec<u8>> {
+    // Get the backend salt from the environment variable or a file
+    let backend_salt =
+        load_secret("BACKEND_SALT")?.ok_or("BACKEND_SALT must be set".to_string())?;
     let backend_salt = URL_SAFE_NO_PAD
         .decode(&backend_salt)
-        .expect("Invalid BACKEND_SALT; must be valid Base64");
+        .map_err(|_| "Invalid BACKEND_SALT; must be valid Base64".to_string())?;
 
-    assert!(backend_salt.len() == 8, "BACKEND_SALT must be 8 bytes long");
+    if backend_salt.len() != 8 {
+        return Err("BACKEND_SALT must be 8 bytes long".to_string().into());
+    }
 
-    backend_salt
+    Ok(backend_salt)
 }
 
-pub fn load_public_key() -> DecodingKey {
-    let jwt_public_key_path = env::var("JWT_PUBLIC_KEY_PATH").unwrap_or("key.pub".to_string());
-    let public_key_pem =
-        fs::read_to_string(jwt_public_key_path).expect("Failed to read public key");
-    let decoding_key = DecodingKey::from_ed_pem(public_key_pem.as_bytes())
-        .expect("Failed to create DecodingKey from public key");
-    decoding_key
+pub fn load_public_key(filepath: &str, algorithm: Algorithm) -> ConfigResult<DecodingKey> {
+    let public_key_pem = fs::read_to_string(filepath)
+        .map_err(|err| format!("Failed to read the JWT public key {}: {}", filepath, err))?;
+    let decoding_key = match algorithm {
+        Algorithm::ES256 => DecodingKey::from_ec_pem(public_key_pem.as_bytes()),
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(public_key_pem.as_bytes()),
+        _ => DecodingKey::from_ed_pem(public_key_pem.as_bytes()),
+    };
+    Ok(decoding_key.map_err(|err| format!("Invalid JWT public key {}: {}", filepath, err))?)
 }
 
-pub fn load_cl_filepath() -> String {
-    let cl_filepath = env::var("CL_FILEPATH").unwrap_or("cl.csv".to_string());
-    cl_filepath
+pub fn load_jwt_config(auth: &AuthConfig) -> ConfigResult<JwtConfig> {
+    let algorithms = auth
+        .jwt_algorithms
+        .iter()
+        .map(|name| {
+            auth::parse_algorithm(name)
+                .ok_or_else(|| format!("Invalid JWT algorithm {:?}", name).into())
+        })
+        .collect::<ConfigResult<Vec<_>>>()?;
+
+    // name or name=value
+    let required_claims = auth
+        .jwt_required_claims
+        .iter()
+        .map(|claim| match claim.split_once('=') {
+            Some((name, value)) => RequiredClaim {
+                name: name.to_string(),
+                value: Some(value.to_string()),
+            },
+            None => RequiredClaim {
+                name: claim.clone(),
+                value: None,
+            },
+        })
+        .collect();
+
+    Ok(JwtConfig {
+        algorithms,
+        issuers: auth.jwt_issuers.clone(),
+        audiences: auth.jwt_audiences.clone(),
+        leeway_secs: auth.jwt_leeway_secs,
+        required_claims,
+    })
 }
 
-pub fn load_vl_filepath() -> String {
-    let vl_filepath = env::var("VL_FILEPATH").unwrap_or("vl.csv".to_string());
-    vl_filepath
+pub fn load_key_store(
+    jwt_config: &JwtConfig,
+    auth: &AuthConfig,
+    paths: &PathConfig,
+    reload_status: &ReloadStatus,
+) -> ConfigResult<KeyStore> {
+    // With paths.jwks set, keys come from a JWKS file that is reloaded when
+    // it changes. Otherwise the single PEM key is used for every token.
+    let Some(jwks_filepath) = &paths.jwks else {
+        let public_key_filepath = paths
+            .jwt_public_key
+            .as_deref()
+            .ok_or("paths.jwt_public_key or paths.jwks is required".to_string())?;
+        return Ok(KeyStore::new(vec![VerificationKey {
+            kid: None,
+            algorithm: jwt_config.algorithms[0],
+            decoding_key: load_public_key(public_key_filepath, jwt_config.algorithms[0])?,
+            not_before: None,
+            not_after: None,
+        }]));
+    };
+
+    let keys = load_jwks(jwks_filepath)
+        .map_err(|err| format!("Failed to load JWKS {}: {}", jwks_filepath, err))?;
+    let key_store = KeyStore::new(keys);
+    info!(
+        "Loaded {} keys from JWKS {}",
+        key_store.len(),
+        jwks_filepath
+    );
+
+    spawn_jwks_reloader(
+        key_store.clone(),
+        jwks_filepath.clone(),
+        Duration::from_secs(auth.jwks_reload_interval_secs),
+        reload_status.clone(),
+    );
+
+    Ok(key_store)
 }
 
-pub fn load_config_filepath() -> String {
-    let config_filepath = env::var("CONFIG_FILEPATH").unwrap_or("voting_config.json".to_string());
-    config_filepath
+pub fn load_jwks(filepath: &str) -> Result<Vec<VerificationKey>> {
+    let contents = fs::read_to_string(filepath)?;
+    parse_jwks(&contents)
+}
+
+pub fn load_tls(
+    tls_config: &TlsConfig,
+    reload_status: &ReloadStatus,
+) -> ConfigResult<rustls::ServerConfig> {
+    let key =
+        tls::load_certified_key(&tls_config.cert_path, &tls_config.key_path).map_err(|err| {
+            format!(
+                "Failed to load the TLS certificate {}: {}",
+                tls_config.cert_path, err
+            )
+        })?;
+    let cert_store = Arc::new(CertStore::new(key));
+    info!("Loaded TLS certificate {}", tls_config.cert_path);
+
+    let client_roots = match &tls_config.client_ca_path {
+        Some(ca_filepath) => {
+            let roots = tls::load_client_roots(ca_filepath)
+                .map_err(|err| format!("Failed to load the client CA {}: {}", ca_filepath, err))?;
+            info!(
+                "Admin routes require a client certificate from {}",
+                ca_filepath
+            );
+            Some(roots)
+        }
+        None => None,
+    };
+    let server_config = tls::server_config(cert_store.clone(), client_roots)
+        .map_err(|err| format!("Failed to build the TLS configuration: {}", err))?;
+
+    spawn_cert_reloader(
+        cert_store,
+        tls_config.cert_path.clone(),
+        tls_config.key_path.clone(),
+        tls_config.reload_interval(),
+        reload_status.clone(),
+    );
+    Ok(server_config)
+}
+
+pub fn load_counting_strategy(
+    name: &str,
+    choices: &[Choice],
+) -> ConfigResult<&'static dyn CountingStrategy> {
+    let strategy = strategies::find_strategy(name).ok_or_else(|| {
+        format!(
+            "Unknown counting_strategy {:?}; must be one of {:?}",
+            name,
+            strategies::strategy_names()
+        )
+    })?;
+
+    if !strategy.supports(choices) {
+        return Err(format!(
+            "counting_strategy {} only supports single character choice keys",
+            name
+        )
+        .into());
+    }
+    // The Counts Workers start from the latest votes the strategy makes
+    if !strategy.makes_latest_votes() {
+        return Err(format!(
+            "counting_strategy {} can't make the Counts Workers' latest votes; use one of {:?}",
+            name,
+            strategies::latest_votes_strategy_names()
+        )
+        .into());
+    }
+
+    Ok(strategy)
+}
+
+// `[METHOD ]PATTERN` entries, see PublicRoute. Anonymous votes carry a
+// credential instead of a token, so those routes are added when
+// credentials are enabled. The health checks are always public; /metrics
+// needs an observer or admin token unless auth.public_routes lists it.
+pub fn load_public_routes(
+    public_routes: &[String],
+    credentials_enabled: bool,
+) -> ConfigResult<Vec<PublicRoute>> {
+    let mut routes: Vec<&str> = public_routes.iter().map(String::as_str).collect();
+    if credentials_enabled {
+        routes.extend(["GET /voting/credentials/key", "POST /voting/anonymous/vote"]);
+    }
+    // Probed by the orchestrator, which has no token
+    routes.extend(["GET /health/live", "GET /health/ready"]);
+    routes
+        .into_iter()
+        .map(|route| {
+            PublicRoute::parse(route)
+                .ok_or_else(|| format!("Invalid public route {:?}", route).into())
+        })
+        .collect()
+}
+
+// None unless paths.credential_key is set
+pub fn load_credential_issuer(paths: &PathConfig) -> ConfigResult<Option<CredentialIssuer>> {
+    let Some(key_path) = &paths.credential_key else {
+        return Ok(None);
+    };
+    let key_pem = fs::read_to_string(key_path)
+        .map_err(|err| format!("Failed to read the credential key {}: {}", key_path, err))?;
+    let private_key = RsaPrivateKey::from_pkcs8_pem(&key_pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&key_pem))
+        .map_err(|_| "paths.credential_key must be an RSA private key PEM".to_string())?;
+
+    let issued_path = paths
+        .credential_issued
+        .as_deref()
+        .ok_or("paths.credential_issued is required with paths.credential_key".to_string())?;
+    let issued = IssuedCredentials::open(issued_path).map_err(|err| {
+        format!(
+            "Failed to open the issued credentials log {}: {}",
+            issued_path, err
+        )
+    })?;
+
+    let issuer = CredentialIssuer::new(private_key, issued)
+        .map_err(|err| format!("Invalid credential key: {}", err))?;
+    info!("Anonymous credentials enabled");
+    Ok(Some(issuer))
+}
+
+// None unless auth.jti_max_uses is set
+pub fn load_token_uses(auth: &AuthConfig, paths: &PathConfig) -> ConfigResult<Option<TokenUses>> {
+    let Some(max_uses) = auth.jti_max_uses else {
+        return Ok(None);
+    };
+    let filepath = paths
+        .jti_store
+        .as_deref()
+        .ok_or("paths.jti_store is required with auth.jti_max_uses".to_string())?;
+    let now = chrono::Utc::now().timestamp();
+    let token_uses = TokenUses::open(filepath, max_uses, now)
+        .map_err(|err| format!("Failed to open the jti store {}: {}", filepath, err))?;
+
+    spawn_token_uses_pruner(
+        token_uses.clone(),
+        Duration::from_secs(auth.jti_prune_interval_secs),
+    );
+
+    Ok(Some(token_uses))
+}
+
+pub fn spawn_token_uses_pruner(token_uses: TokenUses, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let token_uses = token_uses.clone();
+            let pruned = web::block(move || token_uses.prune(chrono::Utc::now().timestamp())).await;
+            match pruned {
+                Ok(Ok(0)) => {}
+                Ok(Ok(pruned)) => info!("Pruned {} expired token ids", pruned),
+                Ok(Err(err)) => error!("Failed to prune token ids: {}", err),
+                Err(err) => error!("Failed to prune token ids: {}", err),
+            }
+        }
+    });
+}
+
+pub fn load_rate_limits(config: &RateLimitConfig) -> ConfigResult<RateLimits> {
+    let load_limiter = |field: &str, limit: &Option<String>| -> ConfigResult<Option<RateLimiter>> {
+        let Some(limit) = limit else {
+            return Ok(None);
+        };
+        let limiter =
+            RateLimiter::parse(limit).ok_or_else(|| format!("Invalid {} {:?}", field, limit))?;
+        info!("Rate limiting votes with {} = {}", field, limit);
+        Ok(Some(limiter))
+    };
+    let rate_limits = RateLimits {
+        voter: load_limiter("rate_limits.voter", &config.voter)?,
+        ip: load_limiter("rate_limits.ip", &config.ip)?,
+        trust_forwarded: config.trust_forwarded,
+    };
+
+    if rate_limits.voter.is_some() || rate_limits.ip.is_some() {
+        spawn_rate_limits_pruner(
+            rate_limits.clone(),
+            Duration::from_secs(config.prune_interval_secs),
+        );
+    }
+    Ok(rate_limits)
+}
+
+pub fn spawn_rate_limits_pruner(rate_limits: RateLimits, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let pruned = rate_limits.prune(std::time::Instant::now());
+            if pruned > 0 {
+                info!("Pruned {} refilled rate limit buckets", pruned);
+            }
+        }
+    });
+}
+
+pub fn spawn_jwks_reloader(
+    key_store: KeyStore,
+    jwks_filepath: String,
+    interval: Duration,
+    reload_status: ReloadStatus,
+) {
+    let modified_at = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    tokio::spawn(async move {
+        let mut last_modified: Option<SystemTime> = modified_at(&jwks_filepath);
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let modified = modified_at(&jwks_filepath);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            // Keep the current keys if the new file can't be used
+            let reloaded = match load_jwks(&jwks_filepath) {
+                Ok(keys) if !keys.is_empty() => {
+                    info!("Reloaded {} keys from JWKS {}", keys.len(), jwks_filepath);
+                    key_store.replace(keys);
+                    true
+                }
+                Ok(_) => {
+                    error!("JWKS {} has no keys; keeping current keys", jwks_filepath);
+                    false
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to reload JWKS {}: {}; keeping current keys",
+                        jwks_filepath, err
+                    );
+                    false
+                }
+            };
+            reload_status.set_valid(reloaded);
+        }
+    });
+}
+
+pub fn spawn_cert_reloader(
+    cert_store: Arc<CertStore>,
+    cert_filepath: String,
+    key_filepath: String,
+    interval: Duration,
+    reload_status: ReloadStatus,
+) {
+    let modified_at = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    tokio::spawn(async move {
+        let mut last_modified = (modified_at(&cert_filepath), modified_at(&key_filepath));
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let modified = (modified_at(&cert_filepath), modified_at(&key_filepath));
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            // Keep the current certificate if the new files can't be used,
+            // e.g. when only one of them has been replaced so far
+            match tls::load_certified_key(&cert_filepath, &key_filepath) {
+                Ok(key) => {
+                    info!("Reloaded TLS certificate {}", cert_filepath);
+                    reload_status.set_valid(true);
+                    cert_store.replace(key);
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to reload TLS certificate {}: {}; keeping current certificate",
+                        cert_filepath, err
+                    );
+                    reload_status.set_valid(false);
+                }
+            }
+        }
+    });
+}
+
+// Per-vote details are only logged at `debug`. RUST_LOG is still read when
+// LOG_LEVEL isn't set.
+pub fn load_telemetry_config() -> ConfigResult<TelemetryConfig> {
+    let log_level = env::var("LOG_LEVEL")
+        .or_else(|_| env::var("RUST_LOG"))
+        .unwrap_or("info".to_string());
+    let log_filter = EnvFilter::try_new(&log_level)
+        .map_err(|err| format!("Invalid LOG_LEVEL {:?}: {}", log_level, err))?;
+    let log_format = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => LogFormat::Json,
+        Ok("text") | Err(_) => LogFormat::Text,
+        Ok(other) => {
+            return Err(format!("Invalid LOG_FORMAT {:?}; must be text or json", other).into())
+        }
+    };
+
+    let otlp = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => {
+            let otlp_level =
+                env::var("OTLP_LEVEL").unwrap_or("info,voterium_backend=debug".to_string());
+            let filter = EnvFilter::try_new(&otlp_level)
+                .map_err(|err| format!("Invalid OTLP_LEVEL {:?}: {}", otlp_level, err))?;
+            let tracer_provider = otlp_tracer_provider(&endpoint).map_err(|err| {
+                format!(
+                    "Invalid OTEL_EXPORTER_OTLP_ENDPOINT {:?}: {}",
+                    endpoint, err
+                )
+            })?;
+            Some(OtlpConfig {
+                tracer_provider,
+                filter,
+            })
+        }
+        Err(_) => None,
+    };
+
+    Ok(TelemetryConfig {
+        log_filter,
+        log_format,
+        log_span_events: env::var("LOG_SPAN_EVENTS").is_ok_and(|v| v == "true"),
+        otlp,
+    })
 }
 
 pub async fn spawn_ledger_worker(
     cl_filepath: &str,
     vl_filepath: &str,
-) -> tokio::sync::mpsc::Sender<LedgerWorkerMsg> {
-    let (tx, rx) = tokio::sync::mpsc::channel(10_000);
+    capacity: usize,
+) -> (tokio::sync::mpsc::Sender<LedgerWorkerMsg>, WorkerStatus) {
+    let (tx, rx) = tokio::sync::mpsc::channel(capacity);
+    let status = WorkerStatus::new("Ledger Worker");
     let cl_filepath = cl_filepath.to_owned();
     let vl_filepath = vl_filepath.to_owned();
-    tokio::spawn(async move {
-        run_ledger_worker(rx, &cl_filepath, &vl_filepath)
-            .await
-            .expect("Ledger worker failed");
+    supervise(status.clone(), rx, move |mut rx, status| {
+        let (cl_filepath, vl_filepath) = (cl_filepath.clone(), vl_filepath.clone());
+        async move { run_ledger_worker(&mut rx, &cl_filepath, &vl_filepath, &status).await }
     });
-    tx
+    (tx, status)
 }
 
-pub async fn spawn_count_worker(
+pub async fn spawn_count_workers(
     choices: Vec<Choice>,
     cl_filepath: &str,
-) -> tokio::sync::mpsc::Sender<CountWorkerMsg> {
-    let (tx, rx) = tokio::sync::mpsc::channel(10_000);
-    let cl_filepath = cl_filepath.to_owned();
-    tokio::spawn(async move {
-        run_counts_worker(rx, &cl_filepath, &choices)
-            .await
-            .expect("Count worker failed");
-    });
-    tx
+    peppers: &Peppers,
+    n_shards: usize,
+    strategy: &'static dyn CountingStrategy,
+    capacity: usize,
+    metrics: &Metrics,
+) -> Result<CountWorkers> {
+    // The CL is loaded and upgraded to the current pepper once, and each
+    // shard starts from its own partition of the latest votes made by the
+    // counting strategy
+    let sharded_latest_votes = {
+        let cl_data = load_current_cl(cl_filepath, peppers)?;
+        strategy.latest_votes(&cl_data, &choices, n_shards)?
+    };
+    info!("Latest votes made with {}", strategy.name());
+
+    let shards = sharded_latest_votes
+        .into_iter()
+        .enumerate()
+        .map(|(shard, latest_votes)| {
+            let (tx, rx) = tokio::sync::mpsc::channel(capacity);
+            let counts_snapshot = CountsSnapshot::default();
+            let choices = choices.clone();
+            let cl_filepath = cl_filepath.to_owned();
+            let peppers = peppers.clone();
+            let worker_snapshot = counts_snapshot.clone();
+            let metrics = metrics.clone();
+            let status = WorkerStatus::new(&format!("Counts Worker {}/{}", shard + 1, n_shards));
+            // Only the first run gets them; a restarted worker reloads the CL
+            let mut initial_latest_votes = Some(latest_votes);
+            supervise(status.clone(), rx, move |mut rx, status| {
+                let (cl_filepath, peppers, choices) =
+                    (cl_filepath.clone(), peppers.clone(), choices.clone());
+                let (worker_snapshot, metrics) = (worker_snapshot.clone(), metrics.clone());
+                let initial_latest_votes = initial_latest_votes.take();
+                async move {
+                    run_counts_worker(
+                        &mut rx,
+                        &cl_filepath,
+                        &peppers,
+                        &choices,
+                        strategy,
+                        worker_snapshot,
+                        shard,
+                        n_shards,
+                        initial_latest_votes,
+                        &status,
+                        &metrics,
+                    )
+                    .await
+                }
+            });
+            CountWorkerShard {
+                sender: tx,
+                counts_snapshot,
+                status,
+            }
+        })
+        .collect();
+
+    Ok(CountWorkers { shards })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::with_env;
+
+    fn telemetry_errors(vars: &[(&str, &str)]) -> Vec<String> {
+        match with_env(vars, load_telemetry_config) {
+            Ok(_) => vec![],
+            Err(err) => err.errors,
+        }
+    }
+
+    #[test]
+    fn test_invalid_telemetry_config_is_refused() {
+        let errors = telemetry_errors(&[("LOG_LEVEL", "info,[{")]);
+        assert!(errors[0].starts_with("Invalid LOG_LEVEL"), "{:?}", errors);
+
+        let errors = telemetry_errors(&[("OTEL_EXPORTER_OTLP_ENDPOINT", "not a url")]);
+        assert!(
+            errors[0].starts_with("Invalid OTEL_EXPORTER_OTLP_ENDPOINT"),
+            "{:?}",
+            errors
+        );
+
+        let errors = telemetry_errors(&[
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://127.0.0.1:4318"),
+            ("OTLP_LEVEL", "[["),
+        ]);
+        assert!(errors[0].starts_with("Invalid OTLP_LEVEL"), "{:?}", errors);
+
+        assert!(telemetry_errors(&[("LOG_LEVEL", "info,voterium_backend=debug")]).is_empty());
+    }
 }
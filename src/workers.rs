@@ -1,21 +1,40 @@
+use crate::counting::strategies::CountingStrategy;
 use crate::counting::utils::{
     counts_from_latest_votes, indexed_counts_to_vote_counts, make_choices_lookup,
-    make_latest_votes_hashmap,
+    INVALID_CHOICE_IDX,
 };
 use crate::errors::Result;
-use crate::ledgers::load_cl;
-use crate::models::{Choice, CountWorkerBallot, CountWorkerMsg, LedgerWorkerMsg, VoteCount};
-use log::info;
+use crate::ledgers::{load_current_cl, truncate_partial_records};
+use crate::metrics::Metrics;
+use crate::models::{
+    Choice, CountWorkerBallot, CountWorkerMsg, CountsSnapshot, LedgerWorkerMsg, VoteCounts,
+};
+use crate::peppers::Peppers;
+use crate::supervisor::WorkerStatus;
 use rustc_hash::FxHashMap;
 use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc::Receiver;
+use tracing::{debug, info};
+
+// Upper bound on votes applied before the counts snapshot is republished,
+// so results stay fresh even when the queue never drains
+const MAX_VOTES_PER_SNAPSHOT: usize = 1_024;
+
+// Most messages written between syncs
+const LEDGER_BATCH_SIZE: usize = 1_024;
 
 
 pub async fn run_ledger_worker(
-    mut rx: Receiver<LedgerWorkerMsg>,
-    cl_filepath: impl AsRef<std::path::Path>,
-    vl_filepath: impl AsRef<std::path::Path>,
+    rx: &mut Receiver<LedgerWorkerMsg>,
+    cl_filepath: &str,
+    vl_filepath: &str,
+    status: &WorkerStatus,
 ) -> Result<()> {
+    // A failed run may have left half a record behind
+    truncate_partial_records(cl_filepath, vl_filepath)?;
+
     let mut cl = std::fs::OpenOptions::new()
         .create(true)
         .append(true)
@@ -26,77 +45,274 @@ pub async fn run_ledger_worker(
         .append(true)
         .open(vl_filepath)?;
 
+    status.set_up();
     info!("Ledger Worker started");
-    loop {
-        let msg = rx.recv().await.expect("Should receive task not error");
+    // Write whatever is queued, sync once, then acknowledge the batch. If
+    // anything fails the batch's response channels are dropped, so no
+    // caller is told its vote was written.
+    let mut batch = Vec::with_capacity(LEDGER_BATCH_SIZE);
+    while rx.recv_many(&mut batch, LEDGER_BATCH_SIZE).await > 0 {
+        let (cl_len, vl_len) = (cl.metadata()?.len(), vl.metadata()?.len());
+        if let Err(err) = write_ledger_batch(&mut cl, &mut vl, &batch) {
+            // Take back what was written so the ledgers only hold
+            // acknowledged votes
+            let _ = cl.set_len(cl_len);
+            let _ = vl.set_len(vl_len);
+            return Err(err.into());
+        }
+
+        for msg in batch.drain(..) {
+            let _entered = msg.span.enter();
+            debug!("Written to the ledgers");
+            if let Some(resp) = msg.resp {
+                // The caller may have given up waiting
+                let _ = resp.send(true);
+            }
+        }
+    }
 
+    // Every sender is gone
+    Ok(())
+}
+
+fn write_ledger_batch(
+    cl: &mut std::fs::File,
+    vl: &mut std::fs::File,
+    batch: &[LedgerWorkerMsg],
+) -> std::io::Result<()> {
+    for msg in batch {
         cl.write_all(&msg.cl_line)?;
         vl.write_all(&msg.vl_line)?;
-
-        if let Some(rx) = msg.resp {
-            // If the caller provided a response channel, send a response
-            rx.send(true).expect("Should send response");
-        }
     }
+    cl.sync_data()?;
+    vl.sync_data()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_counts_worker(
-    mut rx: Receiver<CountWorkerMsg>,
-    cl_filepath: impl AsRef<std::path::Path>,
-    choices: &Vec<Choice>,
+    rx: &mut Receiver<CountWorkerMsg>,
+    cl_filepath: &str,
+    peppers: &Peppers,
+    choices: &[Choice],
+    strategy: &dyn CountingStrategy,
+    counts_snapshot: CountsSnapshot,
+    shard: usize,
+    n_shards: usize,
+    initial_latest_votes: Option<FxHashMap<u128, usize>>,
+    status: &WorkerStatus,
+    metrics: &Metrics,
 ) -> Result<()> {
     // The Counts Worker maintains a live vote count in memory and updates them
     // as new votes come in so that Voterium can quickly respond to requests
-    // for the current count
+    // for the current count. The counts are published to `counts_snapshot`
+    // so that readers don't have to queue behind votes on the channel.
+    // With several shards, each worker only counts the voters in `shard`.
+    // The first run starts from the latest votes made when the CL was loaded
+    // for every shard. After a restart the counts are rebuilt from the CL,
+    // which holds every vote sent to this worker since votes are only
+    // counted once written. Either way the latest votes are made by the
+    // configured counting strategy.
 
+    let start_rebuild = Instant::now();
     let choice_idx_map = make_choices_lookup(choices);
 
-    let mut latest_votes = {
-        let cl_data = load_cl(cl_filepath)?;
-        make_latest_votes_hashmap(&cl_data, choice_idx_map.clone())
+    let mut latest_votes = match initial_latest_votes {
+        Some(latest_votes) => latest_votes,
+        None => {
+            let cl_data = load_current_cl(cl_filepath, peppers)?;
+            strategy
+                .latest_votes(&cl_data, choices, n_shards)?
+                .swap_remove(shard)
+        }
     };
 
     let mut vote_counts = {
-        let counts = counts_from_latest_votes(&latest_votes, choices);
-        indexed_counts_to_vote_counts(&counts, choices)
+        let (counts, invalid) = counts_from_latest_votes(&latest_votes, choices);
+        VoteCounts {
+            counts: indexed_counts_to_vote_counts(&counts, choices),
+            invalid,
+        }
     };
 
-    info!("Counts Worker started. Initial counts: {:?}", vote_counts);
-    loop {
-        let msg: CountWorkerMsg = rx.recv().await.expect("Should receive task not error");
+    publish_counts(&counts_snapshot, &vote_counts);
+    metrics.set_counts_rebuild(shard, start_rebuild.elapsed());
+    metrics.set_unique_voters(shard, latest_votes.len());
+    status.set_up();
+
+    info!(
+        "Counts Worker {}/{} started. Initial counts: {:?}, invalid: {}",
+        shard + 1,
+        n_shards,
+        vote_counts.counts,
+        vote_counts.invalid
+    );
+    let mut unpublished_votes = 0;
+    while let Some(msg) = rx.recv().await {
         match msg {
-            CountWorkerMsg::Vote { ballot } => {
-                add_vote(
+            CountWorkerMsg::Vote { ballot, span } => {
+                let _entered = span.enter();
+                let revote = add_vote(
                     &ballot,
                     &choice_idx_map,
                     &mut vote_counts,
                     &mut latest_votes,
                 );
+                debug!(revote, "Vote counted");
+                if revote {
+                    metrics.inc_revotes();
+                } else {
+                    metrics.set_unique_voters(shard, latest_votes.len());
+                }
+
+                // Publish once the queue is drained to avoid cloning the
+                // counts for every vote in a burst
+                unpublished_votes += 1;
+                if rx.is_empty() || unpublished_votes >= MAX_VOTES_PER_SNAPSHOT {
+                    publish_counts(&counts_snapshot, &vote_counts);
+                    unpublished_votes = 0;
+                }
             }
 
             CountWorkerMsg::GetCounts { resp } => {
-                resp.send(vote_counts.clone())
-                    .expect("Should send response");
+                // The caller may have given up waiting
+                let _ = resp.send(vote_counts.clone());
+            }
+
+            CountWorkerMsg::Ping { resp } => {
+                let _ = resp.send(());
             }
         }
     }
+
+    // Every sender is gone
+    Ok(())
 }
 
+fn publish_counts(counts_snapshot: &CountsSnapshot, vote_counts: &VoteCounts) {
+    let mut sorted_counts = vote_counts.clone();
+    sorted_counts.counts.sort_by(|a, b| a.choice.cmp(&b.choice));
+    counts_snapshot.store(Some(Arc::new(sorted_counts)));
+}
+
+// Returns whether the vote replaced the voter's earlier vote
 fn add_vote(
     ballot: &CountWorkerBallot,
     choice_idx_map: &FxHashMap<u8, usize>,
-    vote_counts: &mut Vec<VoteCount>,
+    vote_counts: &mut VoteCounts,
     latest_votes: &mut FxHashMap<u128, usize>,
-) {
+) -> bool {
     if let Some(&choice_idx) = choice_idx_map.get(&ballot.choice_key) {
         // choice_idx_map turns the choice key into an index so that
         // we can count votes in a Vec (fast) instead of a HashMap (slow)
         let old_choice_idx = latest_votes.insert(ballot.user_id_hash, choice_idx);
 
-        if let Some(old_choice_idx) = old_choice_idx {
-            vote_counts[old_choice_idx].count -= 1;
+        match old_choice_idx {
+            Some(INVALID_CHOICE_IDX) => vote_counts.invalid -= 1,
+            Some(old_choice_idx) => vote_counts.counts[old_choice_idx].count -= 1,
+            None => {}
         }
 
-        vote_counts[choice_idx].count += 1;
+        vote_counts.counts[choice_idx].count += 1;
+        return old_choice_idx.is_some();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting::strategies::find_strategy;
+    use crate::counting::utils::RECORD_SIZE;
+    use crate::test_support::{peppers, temp_filepath};
+    use crate::utils::load_voting_config;
+    use std::fs;
+    use std::time::Duration;
+
+    // Runs a Counts Worker as it's run after a restart, rebuilding its
+    // counts from the CL, and returns them
+    async fn rebuilt_counts(cl_filepath: &str) -> VoteCounts {
+        let choices = load_voting_config("examples/voting_config_ABC.json")
+            .unwrap()
+            .choices;
+        let strategy = find_strategy("count_votes_35").unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let counts_snapshot = CountsSnapshot::default();
+        let status = WorkerStatus::new("Counts Worker 1/1");
+
+        let worker = {
+            let (cl_filepath, counts_snapshot, status) = (
+                cl_filepath.to_string(),
+                counts_snapshot.clone(),
+                status.clone(),
+            );
+            tokio::spawn(async move {
+                run_counts_worker(
+                    &mut rx,
+                    &cl_filepath,
+                    &peppers(),
+                    &choices,
+                    strategy,
+                    counts_snapshot,
+                    0,
+                    1,
+                    None,
+                    &status,
+                    &Metrics::new(),
+                )
+                .await
+            })
+        };
+        while !status.is_up() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        drop(tx);
+        worker.await.unwrap().unwrap();
+        (*counts_snapshot.load_full().unwrap()).clone()
+    }
+
+    #[actix_web::test]
+    async fn test_restart_ignores_a_partial_record() {
+        let cl_filepath = temp_filepath("workers", "partial_record", "csv");
+        let records = fs::read("examples/cl_10.csv").unwrap();
+        fs::write(&cl_filepath, &records).unwrap();
+        let counts = rebuilt_counts(&cl_filepath).await;
+        assert_eq!(counts.invalid, 0);
+
+        // As if the Ledger Worker were part way through appending a record
+        let mut partial = records.clone();
+        partial.extend_from_slice(&records[..RECORD_SIZE / 2]);
+        fs::write(&cl_filepath, &partial).unwrap();
+        assert_eq!(rebuilt_counts(&cl_filepath).await, counts);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[actix_web::test]
+    async fn test_ledger_worker_rolls_back_a_failed_batch() {
+        let cl_filepath = temp_filepath("workers", "rollback", "csv");
+        let records = fs::read("examples/cl_10.csv").unwrap();
+        fs::write(&cl_filepath, &records).unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let (written_tx, written_rx) = tokio::sync::oneshot::channel();
+        tx.send(LedgerWorkerMsg {
+            vl_line: b"vote-1,A\n".to_vec(),
+            cl_line: records[..RECORD_SIZE].to_vec(),
+            resp: Some(written_tx),
+            span: tracing::Span::none(),
+        })
+        .await
+        .unwrap();
+
+        // Every write to /dev/full fails, so the VL write fails after the
+        // CL's has succeeded
+        let status = WorkerStatus::new("Ledger Worker");
+        assert!(
+            run_ledger_worker(&mut rx, &cl_filepath, "/dev/full", &status)
+                .await
+                .is_err()
+        );
+        assert_eq!(fs::read(&cl_filepath).unwrap(), records);
+        // and the caller isn't told the vote was written
+        assert!(written_rx.await.is_err());
     }
 }